@@ -0,0 +1,180 @@
+use super::fitbit::ActivityOutput;
+use super::view::DurationSource;
+
+/// Values this close count as equal, so that a distance of 10.0 km stored as 9.9999995
+/// meets `distance>=10`. Below the 0.01 km and 0.01 min. the report shows.
+const TOLERANCE: f32 = 0.005;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    /// km
+    Distance,
+    /// minutes
+    Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comparator {
+    Ge,
+    Le,
+    Gt,
+    Lt,
+    Eq,
+}
+
+/// `--post-if` condition: `<field><comparator><number>` (e.g. `distance>=10`) or `pr`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PostCondition {
+    Compare {
+        field: Field,
+        comparator: Comparator,
+        value: f32,
+    },
+    PersonalRecord,
+}
+
+const COMPARATORS: [(&str, Comparator); 5] = [
+    (">=", Comparator::Ge),
+    ("<=", Comparator::Le),
+    ("==", Comparator::Eq),
+    (">", Comparator::Gt),
+    ("<", Comparator::Lt),
+];
+
+pub fn parse(s: &str) -> Result<PostCondition, String> {
+    let s = s.trim();
+    if s == "pr" {
+        return Ok(PostCondition::PersonalRecord);
+    }
+    let (field, comparator, value) = COMPARATORS
+        .iter()
+        .find_map(|(op, comparator)| {
+            s.split_once(op)
+                .map(|(field, value)| (field.trim(), comparator.clone(), value.trim()))
+        })
+        .ok_or(format!("no comparator in condition: {}", s))?;
+    let field = match field {
+        "distance" => Field::Distance,
+        "duration" => Field::Duration,
+        _ => return Err(format!("unknown field in condition: {}", field)),
+    };
+    let value = value
+        .parse::<f32>()
+        .map_err(|_| format!("not a number in condition: {}", value))?;
+    Ok(PostCondition::Compare {
+        field,
+        comparator,
+        value,
+    })
+}
+
+impl PostCondition {
+    /// The duration is taken from `duration_source`, like the one the report shows.
+    pub fn is_met(&self, output: &ActivityOutput, duration_source: DurationSource) -> bool {
+        match self {
+            PostCondition::PersonalRecord => output.personal_record == Some(true),
+            PostCondition::Compare {
+                field,
+                comparator,
+                value,
+            } => {
+                let actual = match field {
+                    Field::Distance => output.distance.unwrap_or(0.0),
+                    Field::Duration => duration_source.duration(output) as f32 / 60.0 / 1000.0,
+                };
+                let equal = (actual - value).abs() < TOLERANCE;
+                match comparator {
+                    Comparator::Ge => equal || actual > *value,
+                    Comparator::Le => equal || actual < *value,
+                    Comparator::Gt => !equal && actual > *value,
+                    Comparator::Lt => !equal && actual < *value,
+                    Comparator::Eq => equal,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("pr"), Ok(PostCondition::PersonalRecord));
+        assert_eq!(
+            parse("distance>=10"),
+            Ok(PostCondition::Compare {
+                field: Field::Distance,
+                comparator: Comparator::Ge,
+                value: 10.0,
+            })
+        );
+        assert_eq!(
+            parse("duration < 30.5"),
+            Ok(PostCondition::Compare {
+                field: Field::Duration,
+                comparator: Comparator::Lt,
+                value: 30.5,
+            })
+        );
+        assert!(parse("pace>=5").is_err());
+        assert!(parse("distance=10").is_err());
+        assert!(parse("distance>=ten").is_err());
+    }
+
+    #[test]
+    fn test_is_met() {
+        let output = ActivityOutput {
+            distance: Some(10.5),
+            duration: 45 * 60 * 1000,
+            ..Default::default()
+        };
+        let is_met = |condition: &str, output: &ActivityOutput| {
+            parse(condition)
+                .unwrap()
+                .is_met(output, DurationSource::default())
+        };
+        assert!(is_met("distance>=10", &output));
+        assert!(!is_met("distance>10.5", &output));
+        assert!(is_met("duration<=45", &output));
+        assert!(!PostCondition::PersonalRecord.is_met(&output, DurationSource::default()));
+
+        let output = ActivityOutput {
+            personal_record: Some(true),
+            ..output
+        };
+        assert!(PostCondition::PersonalRecord.is_met(&output, DurationSource::default()));
+    }
+
+    #[test]
+    fn test_is_met_duration_source() {
+        let output = ActivityOutput {
+            distance: Some(10.0),
+            duration: 50 * 60 * 1000,
+            active_duration: Some(44 * 60 * 1000),
+            ..Default::default()
+        };
+        let condition = parse("duration<=45").unwrap();
+        assert!(condition.is_met(&output, DurationSource::Active));
+        assert!(!condition.is_met(&output, DurationSource::Total));
+    }
+
+    #[test]
+    fn test_is_met_tolerance() {
+        let output = ActivityOutput {
+            distance: Some(0.1 + 0.2),
+            ..Default::default()
+        };
+        let is_met = |condition: &str| {
+            parse(condition)
+                .unwrap()
+                .is_met(&output, DurationSource::default())
+        };
+        assert!(is_met("distance==0.3"));
+        assert!(is_met("distance>=0.3"));
+        assert!(is_met("distance<=0.3"));
+        assert!(!is_met("distance>0.3"));
+        assert!(!is_met("distance<0.3"));
+    }
+}
@@ -1,23 +1,169 @@
 use base64::{engine::general_purpose, Engine as _};
 use chrono::{DateTime, Duration, NaiveDate, Utc};
+use regex::Regex;
 use reqwest::{Client, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Mutex;
 use std::{
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
     io::{self, Write},
 };
 
+use super::http::HttpConfig;
+
 #[derive(Debug, Clone)]
 pub struct FitbitApiConfig {
     pub base_url: String,
+    /// Version in the resource paths, one of `API_VERSIONS`; OAuth paths are not versioned.
+    pub api_version: String,
     pub client_id: String,
     pub client_secret: String,
+    pub http: HttpConfig,
+    /// Refresh the access token when it expires within this margin.
+    pub token_skew: Duration,
+    /// Which activities count as runs.
+    pub activity: ActivityMatcher,
+}
+
+/// Something the run could not be summarized as usual for. Informational only: the run is
+/// still reported, without the parts the warning names.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Warning {
+    /// The activity log has no usable track, so there are no splits and zones.
+    TooFewTrackpoints,
+    MissingHeartRate,
+    /// Trackpoints without heart rate, each filled in from the last one recorded before it
+    /// or, before the first recorded one, from that.
+    HeartRateGaps {
+        trackpoints: usize,
+    },
+    /// Distances (km) beyond `--distance-tolerance` apart.
+    DistanceDiscrepancy {
+        reported: f32,
+        gps: f32,
+    },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::TooFewTrackpoints => write!(
+                f,
+                "the activity log has too few trackpoints, splits and heart rate zones omitted"
+            ),
+            Warning::MissingHeartRate => {
+                write!(
+                    f,
+                    "the activity log has no heart rate, heart rate zones omitted"
+                )
+            }
+            Warning::HeartRateGaps { trackpoints } => write!(
+                f,
+                "{} {} no heart rate, filled in from the last recorded one before them",
+                trackpoints,
+                if *trackpoints == 1 {
+                    "trackpoint has"
+                } else {
+                    "trackpoints have"
+                }
+            ),
+            Warning::DistanceDiscrepancy { reported, gps } => write!(
+                f,
+                "the GPS distance {:.3} km differs from the reported {:.3} km by {:+.1}%",
+                gps,
+                reported,
+                (gps - reported) / reported * 100.0
+            ),
+        }
+    }
+}
+
+/// Versions of the Web API resource paths, e.g. `/1.2/user/-/...`.
+pub const API_VERSIONS: [&str; 2] = ["1", "1.2"];
+
+pub fn parse_api_version(s: &str) -> std::result::Result<String, String> {
+    if !API_VERSIONS.contains(&s) {
+        return Err(format!(
+            "unknown Fitbit API version {}, expected one of {}",
+            s,
+            API_VERSIONS.join(", ")
+        ));
+    }
+    Ok(s.to_owned())
+}
+
+impl FitbitApiConfig {
+    /// URL of a resource of the logged in user, e.g. `activities/list.json`.
+    fn user_url(&self, path: &str) -> String {
+        format!("{}/{}/user/-/{}", self.base_url, self.api_version, path)
+    }
+}
+
+/// Matches the `activityName` of an activity, exactly or by a regex.
+#[derive(Debug, Clone)]
+pub enum ActivityMatcher {
+    Name(String),
+    Pattern(Regex),
+}
+
+impl ActivityMatcher {
+    fn matches(&self, activity: &Activity) -> bool {
+        match self {
+            ActivityMatcher::Name(name) => activity.activityName == *name,
+            ActivityMatcher::Pattern(pattern) => pattern.is_match(&activity.activityName),
+        }
+    }
 }
 
 pub struct FitbitApi {
     config: FitbitApiConfig,
     client: Client,
+    /// Activity lists fetched in this process, keyed by their query string.
+    activity_cache: Mutex<HashMap<String, Activities>>,
+}
+
+/// What the splits of a run are taken at.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SplitBy {
+    /// Time of every full km.
+    #[default]
+    Distance,
+    /// Distance covered in every segment of this many seconds.
+    Time(u32),
+}
+
+/// Parses `distance` or `time:<seconds>`.
+pub fn parse_split_by(arg: &str) -> std::result::Result<SplitBy, String> {
+    if arg == "distance" {
+        return Ok(SplitBy::Distance);
+    }
+    let seconds = arg
+        .strip_prefix("time:")
+        .ok_or_else(|| format!("expected distance or time:<seconds>, got {}", arg))?;
+    match seconds.parse::<u32>() {
+        Ok(seconds) if seconds > 0 => Ok(SplitBy::Time(seconds)),
+        _ => Err(format!("invalid seconds: {}", seconds)),
+    }
+}
+
+/// Options for how the trackpoints of an activity log are summarized.
+#[derive(Debug, Clone, Default)]
+pub struct SummaryOptions {
+    /// Compute heart rate stats only over trackpoints where the distance increased.
+    pub moving_only: bool,
+    /// Distances in km to report the cumulative time at.
+    pub split_markers: Vec<f64>,
+    /// Window in samples for a moving average over the heart rates before zoning.
+    pub smooth_hr: Option<usize>,
+    /// Logs with fewer trackpoints are not summarized.
+    pub min_trackpoints: usize,
+    pub split_by: SplitBy,
+    /// Resting and max heart rate for the TRIMP; it is not computed without both.
+    pub resting_hr: Option<u32>,
+    pub max_hr: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,37 +180,133 @@ struct AuthorizationTokens {
     expires_at: DateTime<Utc>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ActivityOutput {
+    /// Fitbit log id; `None` for a run read from a TCX file.
+    #[serde(default)]
+    pub log_id: Option<u64>,
     pub start_time: String,
     pub distance: Option<f32>,
     pub duration: u32,
+    // everything below is optional in JSON exported before the field existed
+    /// Milliseconds without pauses, when Fitbit reports it.
+    #[serde(default)]
+    pub active_duration: Option<u32>,
+    #[serde(default)]
     pub split_times: Vec<String>,
+    #[serde(default)]
+    pub split_seconds: Vec<u32>,
+    /// Cumulative seconds at each `--split-markers` distance (km) the run reached.
+    #[serde(default)]
+    pub checkpoints: Vec<(f64, u32)>,
+    /// With `--split-by time`, the elapsed seconds at the end of each segment and the km covered in it.
+    #[serde(default)]
+    pub time_splits: Vec<(u32, f64)>,
+    #[serde(default)]
     pub calories: u32,
+    #[serde(default)]
     pub heart_rate_average: u32,
+    #[serde(default)]
     pub heart_rate_max: u32,
+    #[serde(default)]
     pub heart_rate_details: Vec<(String, u32)>,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Distances (km) of the previous runs, most recent first.
+    #[serde(default)]
+    pub history: Vec<f32>,
+    /// Grade-adjusted pace in seconds per km, when the log has altitude data.
+    #[serde(default)]
+    pub grade_adjusted_pace: Option<f64>,
+    /// Whether this is the longest run among all the ones before it; `None` when not checked.
+    #[serde(default)]
+    pub personal_record: Option<bool>,
+    /// Banister's training impulse, when resting and max heart rate are given.
+    #[serde(default)]
+    pub trimp: Option<f64>,
+    /// Steps and distance (km) of the whole day of the run, with `--daily`.
+    #[serde(default)]
+    pub daily_steps: Option<u32>,
+    #[serde(default)]
+    pub daily_distance: Option<f32>,
+    /// Lifetime distance (km) including this run, with `--lifetime`.
+    #[serde(default)]
+    pub lifetime_distance: Option<f64>,
+    /// Distance (km) of the runs of the week up to the day of this run, with `--week-total`.
+    #[serde(default)]
+    pub week_distance: Option<f64>,
+    #[serde(default)]
+    pub warnings: Vec<Warning>,
+    /// Distance (km) of the trackpoints the splits are taken from. It differs from `distance`
+    /// when Fitbit reports a step-based distance; `None` without a track.
+    #[serde(default)]
+    pub gps_distance: Option<f32>,
 }
 
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Activity {
-    logId: u64,
-    activityName: String,
+    pub logId: u64,
+    pub activityName: String,
     activityTypeId: u32,
     pub startTime: String,
     pub distance: Option<f32>,
     pub duration: u32,
+    /// Milliseconds without pauses; not every activity has it.
+    #[serde(default)]
+    pub activeDuration: Option<u32>,
     pub calories: u32,
+    #[serde(default)]
+    pub averageHeartRate: Option<u32>,
+    #[serde(default)]
+    pub note: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 struct Activities {
     activities: Vec<Activity>,
+    #[serde(default)]
+    pagination: Pagination,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Pagination {
+    /// URL of the next page, empty on the last one.
+    #[serde(default)]
+    next: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DailyDistance {
+    activity: String,
+    distance: f32,
+}
+
+#[derive(Deserialize, Debug)]
+struct DailySummaryTotals {
+    steps: u32,
+    #[serde(default)]
+    distances: Vec<DailyDistance>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DailySummaryResponse {
+    summary: DailySummaryTotals,
+}
+
+/// Totals of a whole day, not only of the run.
+#[derive(Debug)]
+pub struct DailySummary {
+    pub steps: u32,
+    /// km
+    pub distance: Option<f32>,
 }
 
 const TOKEN_FILE_PATH: &str = "credentials.json";
 
+/// Labels of the heart rate zones in `heart_rate_details`, from the lowest.
+pub const HEART_RATE_ZONES: [&str; 3] = ["<115", "-150", ">150"];
+
 impl AuthorizationTokens {
     fn from_authorization_response(response: AuthorizationResponse) -> Self {
         let expires_at = Utc::now() + Duration::seconds(response.expires_in as i64);
@@ -76,10 +318,87 @@ impl AuthorizationTokens {
     }
 }
 
+impl Activity {
+    fn trimmed_note(&self) -> Option<String> {
+        self.note
+            .as_ref()
+            .map(|note| note.trim().to_owned())
+            .filter(|note| !note.is_empty())
+    }
+}
+
 impl ActivityOutput {
     fn new(
         activity: &Activity,
         running_activity_summary: &activity::RunningActivitySummary,
+    ) -> Self {
+        let mut output = Self::from_parts(
+            activity.startTime.clone(),
+            activity.distance,
+            activity.duration,
+            activity.calories,
+            running_activity_summary,
+        );
+        output.log_id = Some(activity.logId);
+        output.active_duration = activity.activeDuration;
+        output.note = activity.trimmed_note();
+        output
+    }
+
+    /// Builds the output from the Fitbit activity summary alone, for when the activity log
+    /// cannot be trusted. There are no splits or zones, only the summary's average heart rate.
+    fn from_activity(activity: &Activity) -> Self {
+        Self {
+            log_id: Some(activity.logId),
+            start_time: activity.startTime.clone(),
+            distance: activity.distance,
+            duration: activity.duration,
+            active_duration: activity.activeDuration,
+            calories: activity.calories,
+            heart_rate_average: activity.averageHeartRate.unwrap_or(0),
+            note: activity.trimmed_note(),
+            ..Default::default()
+        }
+    }
+
+    /// Summarizes the activity log, falling back to the activity summary when it has no usable track.
+    fn from_activity_log(activity: &Activity, xml: &str, options: &SummaryOptions) -> Self {
+        match activity::collect_summary(xml, options) {
+            Ok(Some(summary)) => Self::new(activity, &summary),
+            result => {
+                if let Err(e) = result {
+                    eprintln!(
+                        "Failed to parse the activity log of {}. {}",
+                        activity.logId, e
+                    );
+                }
+                let mut output = Self::from_activity(activity);
+                output.warnings.push(Warning::TooFewTrackpoints);
+                output
+            }
+        }
+    }
+
+    /// Builds the output from the activity log alone, without the Fitbit activity summary.
+    /// Distance comes from the last trackpoint, duration from the trackpoint time range
+    /// and calories are unknown.
+    fn from_summary(running_activity_summary: &activity::RunningActivitySummary) -> Self {
+        let duration = running_activity_summary.end_time - running_activity_summary.start_time;
+        Self::from_parts(
+            running_activity_summary.start_time.to_rfc3339(),
+            Some((running_activity_summary.distance_meters / 1000.0) as f32),
+            duration.num_milliseconds() as u32,
+            0,
+            running_activity_summary,
+        )
+    }
+
+    fn from_parts(
+        start_time: String,
+        distance: Option<f32>,
+        duration: u32,
+        calories: u32,
+        running_activity_summary: &activity::RunningActivitySummary,
     ) -> Self {
         let format_split_time = |seconds: &u32| -> String {
             let minutes = seconds / 60;
@@ -88,39 +407,106 @@ impl ActivityOutput {
         };
 
         Self {
-            start_time: activity.startTime.clone(),
-            distance: activity.distance,
-            duration: activity.duration,
+            log_id: None,
+            start_time,
+            distance,
+            duration,
+            active_duration: None,
             split_times: running_activity_summary
                 .split_time_summary
                 .iter()
-                .map(|n| format_split_time(n))
+                .map(format_split_time)
                 .collect::<Vec<String>>(),
-            calories: activity.calories,
+            split_seconds: running_activity_summary.split_time_summary.clone(),
+            checkpoints: running_activity_summary.checkpoint_summary.clone(),
+            time_splits: running_activity_summary
+                .time_split_summary
+                .iter()
+                .map(|(seconds, meters)| (*seconds, meters / 1000.0))
+                .collect(),
+            calories,
             heart_rate_average: running_activity_summary.heart_rate_summary.average,
             heart_rate_max: running_activity_summary.heart_rate_summary.max,
             heart_rate_details: running_activity_summary.heart_rate_summary.details.clone(),
+            note: None,
+            history: vec![],
+            grade_adjusted_pace: running_activity_summary.grade_adjusted_pace,
+            personal_record: None,
+            trimp: running_activity_summary.trimp,
+            daily_steps: None,
+            daily_distance: None,
+            lifetime_distance: None,
+            week_distance: None,
+            warnings: running_activity_summary.warnings.clone(),
+            gps_distance: Some((running_activity_summary.distance_meters / 1000.0) as f32),
+        }
+    }
+
+    /// How much the trackpoint distance differs from the reported one, in % of the reported one.
+    pub fn distance_discrepancy(&self) -> Option<f64> {
+        let (reported, gps) = (self.distance? as f64, self.gps_distance? as f64);
+        if reported <= 0.0 {
+            return None;
         }
+        Some((gps - reported) / reported * 100.0)
     }
 }
 
+#[cfg(test)]
+impl ActivityOutput {
+    /// Output of the activity log of the fixture along with a synthetic activity summary
+    /// agreeing with it, as the API would give them for a run logged with a watch.
+    pub fn from_fixture(path: &str, options: &SummaryOptions) -> Self {
+        let content = fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let summary = activity::collect_summary(&content, options)
+            .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path, e))
+            .unwrap_or_else(|| panic!("Not enough trackpoints in {}", path));
+        let duration = (summary.end_time - summary.start_time).num_milliseconds() as u32;
+        let activity = Activity {
+            logId: 1,
+            activityName: "Run".to_owned(),
+            activityTypeId: 90009,
+            startTime: summary.start_time.to_rfc3339(),
+            distance: Some((summary.distance_meters / 1000.0) as f32),
+            duration,
+            activeDuration: Some(duration),
+            calories: 150,
+            averageHeartRate: Some(summary.heart_rate_summary.average),
+            note: Some("golden run".to_owned()),
+        };
+        Self::new(&activity, &summary)
+    }
+}
+
+/// Summarizes a TCX activity log read from somewhere other than the Fitbit API.
+pub fn summarize_tcx(
+    content: &str,
+    options: &SummaryOptions,
+) -> std::result::Result<Option<ActivityOutput>, quick_xml::DeError> {
+    Ok(activity::collect_summary(content, options)?
+        .map(|summary| ActivityOutput::from_summary(&summary)))
+}
+
 impl FitbitApi {
-    pub fn new(config: FitbitApiConfig) -> Self {
-        Self {
+    pub fn new(config: FitbitApiConfig) -> Result<Self> {
+        let client = config.http.client()?;
+        Ok(Self {
             config,
-            client: Client::new(),
-        }
+            client,
+            activity_cache: Mutex::new(HashMap::new()),
+        })
     }
 
-    pub async fn access_token(self: &Self) -> Result<Option<String>> {
+    pub async fn access_token(&self) -> Result<Option<String>> {
         let tokens = load_tokens(TOKEN_FILE_PATH);
         if let Some(tokens) = tokens {
-            // 1分余裕をみておく
-            if tokens.expires_at > Utc::now() + Duration::seconds(60) {
+            // 期限切れ直前のトークンは使わず、余裕をみて更新する
+            if tokens.expires_at > Utc::now() + self.config.token_skew {
                 return Ok(Some(tokens.access_token));
             }
             let res = self.refresh_token(&tokens.refresh_token).await?;
-            if let None = res {
+            if res.is_none() {
                 return Ok(None);
             }
             let tokens = AuthorizationTokens::from_authorization_response(res.unwrap());
@@ -141,75 +527,244 @@ impl FitbitApi {
     }
 
     pub async fn fetch_latest_run_activity(
-        self: &Self,
+        &self,
         after_date: &NaiveDate,
         token: &String,
+        options: &SummaryOptions,
     ) -> Result<Option<ActivityOutput>> {
+        let activities = self.fetch_run_activities(after_date, token).await?;
+        match activities.first() {
+            Some(activity) => Ok(Some(self.fetch_run_output(activity, token, options).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Runs logged after the date, most recent first.
+    pub async fn fetch_run_activities(
+        &self,
+        after_date: &NaiveDate,
+        token: &String,
+    ) -> Result<Vec<Activity>> {
         let query_params = [
             ("afterDate", after_date.format("%Y-%m-%d").to_string()),
             ("sort", "desc".to_owned()),
             ("offset", "0".to_owned()),
             ("limit", "100".to_owned()),
         ];
-        let fitbit_url = format!("{}/1/user/-/activities/list.json", &self.config.base_url);
+        let activities = self.fetch_activities(&query_params, token).await?;
+        Ok(activities
+            .into_iter()
+            .filter(|a| self.config.activity.matches(a))
+            .collect())
+    }
+
+    /// Every run logged after the date, oldest first, following the pages of the list.
+    pub async fn fetch_all_run_activities(
+        &self,
+        after_date: &NaiveDate,
+        token: &String,
+    ) -> Result<Vec<Activity>> {
+        const PAGE_SIZE: usize = 100;
+        let mut runs = vec![];
+        let mut offset = 0;
+        loop {
+            let query_params = [
+                ("afterDate", after_date.format("%Y-%m-%d").to_string()),
+                ("sort", "asc".to_owned()),
+                ("offset", offset.to_string()),
+                ("limit", PAGE_SIZE.to_string()),
+            ];
+            let activities = self.fetch_activities(&query_params, token).await?;
+            let page_len = activities.len();
+            runs.extend(
+                activities
+                    .into_iter()
+                    .filter(|a| self.config.activity.matches(a)),
+            );
+            if page_len < PAGE_SIZE {
+                return Ok(runs);
+            }
+            offset += PAGE_SIZE;
+        }
+    }
+
+    /// Fetches the activity log of the run and summarizes it.
+    pub async fn fetch_run_output(
+        &self,
+        activity: &Activity,
+        token: &String,
+        options: &SummaryOptions,
+    ) -> Result<ActivityOutput> {
+        let xml = self
+            .fetch_activity_log(&activity.logId.to_string(), token)
+            .await?;
+        Ok(ActivityOutput::from_activity_log(activity, &xml, options))
+    }
+
+    /// Distances (km) of up to `count` runs started before `before` (RFC 3339), most recent
+    /// first, following the `next` link of the list until enough are found; every earlier
+    /// run with `None`.
+    pub async fn fetch_previous_run_distances(
+        &self,
+        before: &str,
+        count: Option<usize>,
+        token: &String,
+    ) -> Result<Vec<f32>> {
+        let before_date = DateTime::parse_from_rfc3339(before)
+            .expect("start time must be RFC 3339.")
+            .naive_local();
+        let query_params = [
+            (
+                "beforeDate",
+                before_date.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            ),
+            ("sort", "desc".to_owned()),
+            ("offset", "0".to_owned()),
+            ("limit", "100".to_owned()),
+        ];
+        let mut page = self
+            .fetch_activity_page(
+                &self.config.user_url("activities/list.json"),
+                &query_params,
+                token,
+            )
+            .await?;
+        let mut distances = vec![];
+        loop {
+            distances.extend(
+                page.activities
+                    .iter()
+                    .filter(|a| self.config.activity.matches(a))
+                    .filter_map(|a| a.distance),
+            );
+            if let Some(count) = count.filter(|count| distances.len() >= *count) {
+                distances.truncate(count);
+                return Ok(distances);
+            }
+            if page.pagination.next.is_empty() || page.activities.is_empty() {
+                return Ok(distances);
+            }
+            page = self
+                .fetch_activity_page(&page.pagination.next, &[], token)
+                .await?;
+        }
+    }
+
+    async fn fetch_activities(
+        &self,
+        query_params: &[(&str, String)],
+        token: &String,
+    ) -> Result<Vec<Activity>> {
+        let fitbit_url = self.config.user_url("activities/list.json");
+        Ok(self
+            .fetch_activity_page(&fitbit_url, query_params, token)
+            .await?
+            .activities)
+    }
+
+    async fn fetch_activity_page(
+        &self,
+        url: &str,
+        query_params: &[(&str, String)],
+        token: &String,
+    ) -> Result<Activities> {
+        let cache_key = query_params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .fold(url.to_owned(), |key, param| format!("{} {}", key, param));
+        if let Some(page) = self.activity_cache.lock().unwrap().get(&cache_key) {
+            return Ok(page.clone());
+        }
+
         let res = self
             .client
-            .get(&fitbit_url)
+            .get(url)
             .header(reqwest::header::AUTHORIZATION, format!("Bearer {}", token))
-            .query(&query_params)
+            .query(query_params)
             .send()
             .await?;
 
-        let activities = res.json::<Activities>().await?.activities;
-        let run_activity = activities.iter().find(|a| a.activityName == "Run");
-        if let Some(activity) = run_activity {
-            let xml = self
-                .fetch_activity_log(&activity.logId.to_string(), token)
-                .await?;
-            let content = activity::collect_summary(&xml).expect("Failed to parse activity log");
+        let page = res.json::<Activities>().await?;
+        self.activity_cache
+            .lock()
+            .unwrap()
+            .insert(cache_key, page.clone());
+        Ok(page)
+    }
 
-            Ok(Some(ActivityOutput::new(&activity, &content)))
-        } else {
-            Ok(None)
-        }
+    pub async fn fetch_daily_summary(
+        &self,
+        date: &NaiveDate,
+        token: &String,
+    ) -> Result<DailySummary> {
+        let url = self
+            .config
+            .user_url(&format!("activities/date/{}.json", date.format("%Y-%m-%d")));
+        let res = self
+            .client
+            .get(&url)
+            .header(reqwest::header::AUTHORIZATION, format!("Bearer {}", token))
+            .send()
+            .await?
+            .error_for_status()?;
+        let summary = res.json::<DailySummaryResponse>().await?.summary;
+        Ok(DailySummary {
+            steps: summary.steps,
+            distance: summary
+                .distances
+                .iter()
+                .find(|d| d.activity == "total")
+                .map(|d| d.distance),
+        })
     }
 
-    async fn fetch_activity_log(self: &Self, log_id: &String, token: &String) -> Result<String> {
-        let url = format!(
-            "{}/1/user/-/activities/{}.tcx",
-            &self.config.base_url, log_id
-        );
+    /// Raw TCX of the activity.
+    pub async fn fetch_activity_log(&self, log_id: &String, token: &String) -> Result<String> {
+        let url = self.config.user_url(&format!("activities/{}.tcx", log_id));
         let res = self
             .client
             .get(&url)
             .header(reqwest::header::AUTHORIZATION, format!("Bearer {}", token))
             .send()
-            .await?;
-        Ok(res.text().await?)
+            .await?
+            .error_for_status()?;
+        res.text().await
     }
 
-    async fn refresh_token(
-        self: &Self,
-        refresh_token: &String,
-    ) -> Result<Option<AuthorizationResponse>> {
-        let basic_auth = general_purpose::STANDARD.encode(
+    /// `Authorization` header value of the OAuth client.
+    fn basic_auth(&self) -> String {
+        let credentials = general_purpose::STANDARD.encode(
             format!("{}:{}", &self.config.client_id, &self.config.client_secret).as_bytes(),
         );
+        format!("Basic {}", credentials)
+    }
+
+    /// Invalidates the token at Fitbit; the stored tokens are left as they are.
+    pub async fn revoke_token(&self, token: &String) -> Result<()> {
+        let fitbit_url = format!("{}/oauth2/revoke", &self.config.base_url);
+        self.client
+            .post(fitbit_url)
+            .header(reqwest::header::AUTHORIZATION, self.basic_auth())
+            .form(&[("token", token)])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn refresh_token(&self, refresh_token: &String) -> Result<Option<AuthorizationResponse>> {
         let fitbit_url = format!("{}/oauth2/token", &self.config.base_url);
 
         let req_form = [
             ("client_id", &self.config.client_id),
             ("grant_type", &"refresh_token".to_owned()),
-            ("refresh_token", &refresh_token),
+            ("refresh_token", refresh_token),
         ];
 
         let res = self
             .client
             .post(fitbit_url)
-            .header(
-                reqwest::header::AUTHORIZATION,
-                format!("Basic {}", basic_auth),
-            )
+            .header(reqwest::header::AUTHORIZATION, self.basic_auth())
             .header(
                 reqwest::header::CONTENT_TYPE,
                 "application/x-www-form-urlencoded",
@@ -224,10 +779,7 @@ impl FitbitApi {
         }
     }
 
-    async fn authorize(self: &Self, code: &String) -> Result<AuthorizationResponse> {
-        let basic_auth = general_purpose::STANDARD.encode(
-            format!("{}:{}", &self.config.client_id, &self.config.client_secret).as_bytes(),
-        );
+    async fn authorize(&self, code: &String) -> Result<AuthorizationResponse> {
         let fitbit_url = format!("{}/oauth2/token", &self.config.base_url);
 
         let req_form = [
@@ -239,10 +791,7 @@ impl FitbitApi {
         let res = self
             .client
             .post(fitbit_url)
-            .header(
-                reqwest::header::AUTHORIZATION,
-                format!("Basic {}", basic_auth),
-            )
+            .header(reqwest::header::AUTHORIZATION, self.basic_auth())
             .header(
                 reqwest::header::CONTENT_TYPE,
                 "application/x-www-form-urlencoded",
@@ -258,31 +807,67 @@ impl FitbitApi {
     }
 }
 
-fn load_tokens(path: &str) -> Option<AuthorizationTokens> {
-    let path = Path::new(path);
-    let file = match OpenOptions::new().read(true).open(path) {
-        Err(_) => return None,
+/// Why the stored tokens cannot be used.
+#[derive(Debug)]
+pub enum TokensError {
+    Missing,
+    Invalid(String),
+}
+
+fn read_tokens(path: &str) -> std::result::Result<AuthorizationTokens, TokensError> {
+    let file = match OpenOptions::new().read(true).open(Path::new(path)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(TokensError::Missing),
+        Err(e) => return Err(TokensError::Invalid(e.to_string())),
         Ok(file) => file,
     };
-    match serde_json::from_reader(file) {
-        Err(_) => None,
+    serde_json::from_reader(file).map_err(|e| TokensError::Invalid(e.to_string()))
+}
+
+/// Tokens stored by the last authorization. A file that exists but cannot be read is
+/// reported before it is ignored, so a corrupt file is not mistaken for a first run.
+fn load_tokens(path: &str) -> Option<AuthorizationTokens> {
+    match read_tokens(path) {
         Ok(tokens) => Some(tokens),
+        Err(TokensError::Missing) => None,
+        Err(TokensError::Invalid(e)) => {
+            eprintln!(
+                "Warning: ignoring invalid {}, authorizing again. {}",
+                path, e
+            );
+            None
+        }
     }
 }
 
+/// Access token stored by the last authorization.
+pub fn stored_access_token() -> std::result::Result<String, TokensError> {
+    read_tokens(TOKEN_FILE_PATH).map(|tokens| tokens.access_token)
+}
+
+/// Deletes the stored tokens, so the next run authorizes again.
+pub fn delete_tokens() -> io::Result<()> {
+    fs::remove_file(TOKEN_FILE_PATH)
+}
+
+/// Writes to a temporary file first and renames it over the path, so an interrupted
+/// write never leaves a partial file behind.
 fn store_tokens(path: &str, tokens: &AuthorizationTokens) {
-    let path = Path::new(path);
-    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
-        Err(_) => File::create(path).expect("Failed to create credentials.json."),
-        Ok(file) => file,
-    };
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path).expect("Failed to create credentials.json.tmp.");
     file.write_all(serde_json::to_string_pretty(tokens).unwrap().as_bytes())
-        .expect("Failed to write to credentials.json.");
+        .expect("Failed to write to credentials.json.tmp.");
+    file.sync_all()
+        .expect("Failed to write to credentials.json.tmp.");
+    fs::rename(&tmp_path, path).expect("Failed to replace credentials.json.");
 }
 
 mod activity {
+    use chrono::{DateTime, FixedOffset};
+    use quick_xml::DeError;
     use serde::{Deserialize, Serialize};
 
+    use super::{SplitBy, SummaryOptions, Warning, HEART_RATE_ZONES};
+
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     struct HeartRateBpm {
@@ -292,11 +877,13 @@ mod activity {
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     struct Trackpoint {
-        heart_rate_bpm: HeartRateBpm,
+        time: DateTime<FixedOffset>,
+        altitude_meters: Option<f64>,
+        heart_rate_bpm: Option<HeartRateBpm>,
         distance_meters: f64,
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    #[derive(Serialize, Deserialize, Debug, Default)]
     #[serde(rename_all = "PascalCase")]
     struct Trackpoints {
         trackpoint: Vec<Trackpoint>,
@@ -305,6 +892,7 @@ mod activity {
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     struct Lap {
+        #[serde(default)]
         track: Trackpoints,
     }
 
@@ -312,7 +900,8 @@ mod activity {
     #[serde(rename_all = "PascalCase")]
     struct Activity {
         id: String,
-        lap: Option<Lap>,
+        #[serde(default)]
+        lap: Vec<Lap>,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -336,38 +925,181 @@ mod activity {
 
     pub struct RunningActivitySummary {
         pub split_time_summary: Vec<u32>,
+        pub checkpoint_summary: Vec<(f64, u32)>,
+        pub time_split_summary: Vec<(u32, f64)>,
         pub heart_rate_summary: HeartRateSummary,
+        pub start_time: DateTime<FixedOffset>,
+        pub end_time: DateTime<FixedOffset>,
+        pub distance_meters: f64,
+        /// Seconds per km adjusted for grade; `None` without altitude data.
+        pub grade_adjusted_pace: Option<f64>,
+        pub trimp: Option<f64>,
+        pub warnings: Vec<Warning>,
     }
 
-    pub fn collect_summary(content: &String) -> Option<RunningActivitySummary> {
-        let database: TrainingCenterDatabase =
-            quick_xml::de::from_str(&content).expect("Failed to parse XML.");
-        let lap = &database.activities.activity.get(0).unwrap().lap;
-        if lap.is_none() {
-            return None;
+    /// Fails when the content is not TCX; `None` when it has too few trackpoints.
+    pub fn collect_summary(
+        content: &str,
+        options: &SummaryOptions,
+    ) -> Result<Option<RunningActivitySummary>, DeError> {
+        let database: TrainingCenterDatabase = quick_xml::de::from_str(content)?;
+        let trackpoint = &combine_trackpoints(database.activities.activity);
+        if trackpoint.is_empty() || trackpoint.len() < options.min_trackpoints {
+            return Ok(None);
         }
 
-        let trackpoint = &lap.as_ref().unwrap().track.trackpoint;
-
         let distance_meters = trackpoint
             .iter()
             .map(|p| p.distance_meters)
             .collect::<Vec<f64>>();
-        let split_time_summary = create_split_time_summary(distance_meters);
+        let (split_time_summary, time_split_summary) = match options.split_by {
+            SplitBy::Distance => (create_split_time_summary(&distance_meters), vec![]),
+            SplitBy::Time(seconds) => (vec![], create_time_split_summary(trackpoint, seconds)),
+        };
+        let checkpoint_summary = create_checkpoint_summary(trackpoint, &options.split_markers);
 
-        let heart_rates = trackpoint
+        let mut warnings = vec![];
+        let recorded = trackpoint
             .iter()
-            .map(|p| p.heart_rate_bpm.value)
-            .collect::<Vec<u32>>();
-        let heart_rate_summary = create_heart_rate_summary(heart_rates);
+            .map(|p| p.heart_rate_bpm.as_ref().map(|hr| hr.value))
+            .collect::<Vec<Option<u32>>>();
+        let missing = recorded.iter().filter(|hr| hr.is_none()).count();
+        if missing == recorded.len() {
+            warnings.push(Warning::MissingHeartRate);
+        } else if missing > 0 {
+            warnings.push(Warning::HeartRateGaps {
+                trackpoints: missing,
+            });
+        }
+        let recorded_heart_rates = fill_heart_rates(&recorded);
+        let heart_rates = match options.smooth_hr {
+            Some(window) if window > 1 => smooth_heart_rates(&recorded_heart_rates, window),
+            _ => recorded_heart_rates.clone(),
+        };
+        let mask = if options.moving_only {
+            create_moving_mask(&distance_meters)
+        } else {
+            vec![true; heart_rates.len()]
+        };
+        let heart_rate_summary = if missing == recorded.len() {
+            HeartRateSummary {
+                average: 0,
+                max: 0,
+                details: vec![],
+            }
+        } else {
+            create_heart_rate_summary(heart_rates, &mask)
+        };
+        let trimp = if missing == recorded.len() {
+            None
+        } else {
+            create_trimp(trackpoint, &recorded_heart_rates, options)
+        };
 
-        Some(RunningActivitySummary {
+        Ok(Some(RunningActivitySummary {
             split_time_summary,
+            checkpoint_summary,
+            time_split_summary,
             heart_rate_summary,
-        })
+            start_time: trackpoint.first().unwrap().time,
+            end_time: trackpoint.last().unwrap().time,
+            distance_meters: *distance_meters.last().unwrap(),
+            grade_adjusted_pace: create_grade_adjusted_pace(trackpoint),
+            trimp,
+            warnings,
+        }))
+    }
+
+    /// Heart rates of the trackpoints, the ones without taking the last one before or, at the
+    /// start, the first one recorded. All 0 when there is none.
+    fn fill_heart_rates(recorded: &[Option<u32>]) -> Vec<u32> {
+        let mut last = recorded.iter().flatten().next().copied().unwrap_or(0);
+        recorded
+            .iter()
+            .map(|hr| {
+                if let Some(hr) = hr {
+                    last = *hr;
+                }
+                last
+            })
+            .collect()
+    }
+
+    /// Trackpoints of every lap of every activity, in order. A run paused and resumed is
+    /// logged as several activities; when one starts its distance over, it is continued
+    /// from where the previous one ended.
+    fn combine_trackpoints(activities: Vec<Activity>) -> Vec<Trackpoint> {
+        let mut combined: Vec<Trackpoint> = vec![];
+        for activity in activities {
+            let points = activity
+                .lap
+                .into_iter()
+                .flat_map(|lap| lap.track.trackpoint)
+                .collect::<Vec<Trackpoint>>();
+            let last_distance = combined.last().map_or(0.0, |p| p.distance_meters);
+            let offset = match points.first() {
+                Some(first) if first.distance_meters < last_distance => last_distance,
+                _ => 0.0,
+            };
+            combined.extend(points.into_iter().map(|mut p| {
+                p.distance_meters += offset;
+                p
+            }));
+        }
+        combined
     }
 
-    fn create_split_time_summary(distance_meters: Vec<f64>) -> Vec<u32> {
+    /// Banister's TRIMP: minutes weighted by the heart rate reserve fraction `r` as
+    /// `r * 0.64 * e^(1.92 r)`, summed over the time between trackpoints.
+    fn create_trimp(
+        trackpoint: &[Trackpoint],
+        heart_rates: &[u32],
+        options: &SummaryOptions,
+    ) -> Option<f64> {
+        let (resting, max) = (options.resting_hr? as f64, options.max_hr? as f64);
+        let trimp = trackpoint
+            .windows(2)
+            .zip(&heart_rates[1..])
+            .map(|(pair, heart_rate)| {
+                let minutes = (pair[1].time - pair[0].time).num_milliseconds() as f64 / 60000.0;
+                let ratio = ((*heart_rate as f64 - resting) / (max - resting)).clamp(0.0, 1.0);
+                minutes * ratio * 0.64 * (1.92 * ratio).exp()
+            })
+            .sum();
+        Some(trimp)
+    }
+
+    /// Energy cost of running (J/kg/m) on a grade, after Minetti et al. (2002).
+    fn grade_cost(grade: f64) -> f64 {
+        let i = grade.clamp(-0.45, 0.45);
+        155.4 * i.powi(5) - 30.4 * i.powi(4) - 43.3 * i.powi(3) + 46.3 * i.powi(2) + 19.5 * i + 3.6
+    }
+
+    /// Elapsed time over the flat-equivalent distance, where every segment counts
+    /// as much as the energy it costs relative to running on the flat.
+    fn create_grade_adjusted_pace(trackpoint: &[Trackpoint]) -> Option<f64> {
+        let altitudes = trackpoint
+            .iter()
+            .map(|p| p.altitude_meters)
+            .collect::<Option<Vec<f64>>>()?;
+        let flat_cost = grade_cost(0.0);
+        let mut flat_meters = 0.0;
+        for i in 1..trackpoint.len() {
+            let distance = trackpoint[i].distance_meters - trackpoint[i - 1].distance_meters;
+            if distance <= 0.0 {
+                continue;
+            }
+            let grade = (altitudes[i] - altitudes[i - 1]) / distance;
+            flat_meters += distance * grade_cost(grade) / flat_cost;
+        }
+        if flat_meters <= 0.0 {
+            return None;
+        }
+        let seconds = (trackpoint.last()?.time - trackpoint.first()?.time).num_milliseconds();
+        Some(seconds as f64 / 1000.0 / (flat_meters / 1000.0))
+    }
+
+    fn create_split_time_summary(distance_meters: &[f64]) -> Vec<u32> {
         let mut split_seconds: Vec<u32> = vec![];
         let mut i = 0;
         for (n, d) in distance_meters.iter().enumerate() {
@@ -386,15 +1118,87 @@ mod activity {
         split_seconds
     }
 
-    fn create_heart_rate_summary(heart_rates: Vec<u32>) -> HeartRateSummary {
+    /// Meters covered in each `interval` seconds from the start, at the first trackpoint
+    /// reaching the end of the segment. The last segment is shorter if the run ends within it.
+    fn create_time_split_summary(trackpoint: &[Trackpoint], interval: u32) -> Vec<(u32, f64)> {
+        let Some(start) = trackpoint.first() else {
+            return vec![];
+        };
+        let elapsed = |p: &Trackpoint| (p.time - start.time).num_seconds() as u32;
+        let total = elapsed(trackpoint.last().unwrap());
+        let mut splits = vec![];
+        let mut previous = start.distance_meters;
+        let mut end = interval;
+        while end < total + interval {
+            let end_seconds = end.min(total);
+            let point = trackpoint
+                .iter()
+                .find(|p| elapsed(p) >= end_seconds)
+                .unwrap();
+            splits.push((end_seconds, point.distance_meters - previous));
+            previous = point.distance_meters;
+            end += interval;
+        }
+        splits
+    }
+
+    /// Elapsed seconds at the first trackpoint reaching each marker (km).
+    /// Markers beyond the total distance are omitted.
+    fn create_checkpoint_summary(trackpoint: &[Trackpoint], markers: &[f64]) -> Vec<(f64, u32)> {
+        let Some(start) = trackpoint.first() else {
+            return vec![];
+        };
+        markers
+            .iter()
+            .filter_map(|marker| {
+                trackpoint
+                    .iter()
+                    .find(|p| p.distance_meters >= marker * 1000.0)
+                    .map(|p| (*marker, (p.time - start.time).num_seconds() as u32))
+            })
+            .collect()
+    }
+
+    /// Trailing moving average over `window` samples (fewer at the start of the series).
+    fn smooth_heart_rates(heart_rates: &[u32], window: usize) -> Vec<u32> {
+        (0..heart_rates.len())
+            .map(|i| {
+                let samples = &heart_rates[(i + 1).saturating_sub(window)..=i];
+                samples.iter().sum::<u32>() / samples.len() as u32
+            })
+            .collect()
+    }
+
+    /// Marks the trackpoints where the distance increased since the previous one.
+    fn create_moving_mask(distance_meters: &[f64]) -> Vec<bool> {
+        let mut mask = vec![false; distance_meters.len()];
+        for i in 1..distance_meters.len() {
+            mask[i] = distance_meters[i] > distance_meters[i - 1];
+        }
+        mask
+    }
+
+    /// Summarizes the heart rates whose mask is set, or all of them if none is set.
+    fn create_heart_rate_summary(heart_rates: Vec<u32>, mask: &[bool]) -> HeartRateSummary {
+        let masked = heart_rates
+            .iter()
+            .zip(mask)
+            .filter(|(_, m)| **m)
+            .map(|(r, _)| *r)
+            .collect::<Vec<u32>>();
+        let heart_rates = if masked.is_empty() {
+            heart_rates
+        } else {
+            masked
+        };
         let average = (heart_rates.iter().sum::<u32>() as f32 / heart_rates.len() as f32) as u32;
         let max = *heart_rates.iter().max().unwrap();
         let mut details: Vec<(String, u32)> = Vec::new();
         for rate in heart_rates.iter() {
             let range = match *rate {
-                r if r < 115 => "<115",
-                r if r >= 115 && r < 150 => "-150",
-                _ => ">150",
+                r if r < 115 => HEART_RATE_ZONES[0],
+                r if (115..150).contains(&r) => HEART_RATE_ZONES[1],
+                _ => HEART_RATE_ZONES[2],
             }
             .to_owned();
             let el = details.iter().find(|d| d.0 == range);
@@ -424,21 +1228,56 @@ mod test {
     fn test_load_tokens() {
         let tokens = load_tokens("credentials.json");
         assert!(tokens.is_some());
-        assert!(tokens.unwrap().access_token.len() > 0);
+        assert!(!tokens.unwrap().access_token.is_empty());
+    }
+
+    #[test]
+    fn test_read_tokens_missing_or_invalid() {
+        let dir = std::env::temp_dir();
+        let missing = dir.join("running_tracker_no_credentials.json");
+        assert!(matches!(
+            read_tokens(missing.to_str().unwrap()),
+            Err(TokensError::Missing)
+        ));
+
+        let partial = dir.join("running_tracker_partial_credentials.json");
+        fs::write(
+            &partial,
+            r#"{"access_token": "a", "expires_at": "2023-05-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        let result = read_tokens(partial.to_str().unwrap());
+        assert!(matches!(result, Err(TokensError::Invalid(e)) if e.contains("refresh_token")));
+        fs::remove_file(partial).unwrap();
+    }
+
+    #[test]
+    fn test_store_tokens_roundtrip() {
+        let path = std::env::temp_dir().join("running_tracker_stored_credentials.json");
+        let path = path.to_str().unwrap();
+        let tokens = AuthorizationTokens {
+            access_token: "access".to_owned(),
+            refresh_token: "refresh".to_owned(),
+            expires_at: Utc::now(),
+        };
+        store_tokens(path, &tokens);
+        let loaded = read_tokens(path).unwrap();
+        assert_eq!(loaded.refresh_token, "refresh");
+        fs::remove_file(path).unwrap();
     }
 
     #[test]
     fn test_collect_summary() {
         let path = "data/55326309608.xml";
         let content =
-            read_to_string(path).expect(format!("Failed to read from file: {}", path).as_str());
-        let summary = activity::collect_summary(&content);
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let summary = activity::collect_summary(&content, &SummaryOptions::default()).unwrap();
         assert!(summary.is_some());
         let heart_rate_summary = &summary.as_ref().unwrap().heart_rate_summary;
         assert_eq!(heart_rate_summary.average, 131);
         assert_eq!(heart_rate_summary.max, 166);
         assert_eq!(
-            heart_rate_summary.details.get(0).unwrap(),
+            heart_rate_summary.details.first().unwrap(),
             &("<115".to_owned(), 265u32)
         );
         assert_eq!(
@@ -452,4 +1291,322 @@ mod test {
         let split_summary = &summary.as_ref().unwrap().split_time_summary;
         assert_ne!(split_summary.len(), 0);
     }
+
+    #[test]
+    fn test_collect_summary_not_tcx() {
+        let content = r#"{"distance": 5.0}"#;
+        assert!(activity::collect_summary(content, &SummaryOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_collect_summary_moving_only() {
+        let path = "data/stationary_segment.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+
+        let full = activity::collect_summary(&content, &SummaryOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(full.heart_rate_summary.average, 110);
+        assert_eq!(full.heart_rate_summary.details.len(), 3);
+
+        let options = SummaryOptions {
+            moving_only: true,
+            ..Default::default()
+        };
+        let moving = activity::collect_summary(&content, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(moving.heart_rate_summary.average, 135);
+        assert_eq!(moving.heart_rate_summary.max, 150);
+        assert_eq!(
+            moving.heart_rate_summary.details,
+            vec![("-150".to_owned(), 3u32), (">150".to_owned(), 1u32)]
+        );
+    }
+
+    #[test]
+    fn test_collect_summary_checkpoints() {
+        let path = "data/stationary_segment.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let options = SummaryOptions {
+            split_markers: vec![0.01, 0.02, 0.05],
+            ..Default::default()
+        };
+        let summary = activity::collect_summary(&content, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.checkpoint_summary, vec![(0.01, 5), (0.02, 7)]);
+    }
+
+    #[test]
+    fn test_collect_summary_trimp() {
+        let path = "data/steady_tempo.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let without_bounds =
+            activity::collect_summary(&content, &SummaryOptions::default()).unwrap();
+        assert_eq!(without_bounds.unwrap().trimp, None);
+
+        let options = SummaryOptions {
+            resting_hr: Some(50),
+            max_hr: Some(190),
+            ..Default::default()
+        };
+        let trimp = activity::collect_summary(&content, &options)
+            .unwrap()
+            .unwrap()
+            .trimp
+            .unwrap();
+        // 30 min. at 150 bpm: r = 100 / 140
+        assert!((trimp - 54.048).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_collect_summary_multiple_activities() {
+        let path = "data/multi_activity.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let summary = activity::collect_summary(&content, &SummaryOptions::default())
+            .unwrap()
+            .unwrap();
+        // one heart rate sample per trackpoint
+        let trackpoint_count: u32 = summary
+            .heart_rate_summary
+            .details
+            .iter()
+            .map(|(_, n)| n)
+            .sum();
+        assert_eq!(trackpoint_count, 12);
+        assert_eq!(summary.distance_meters, 50.0);
+        assert_eq!((summary.end_time - summary.start_time).num_seconds(), 15);
+    }
+
+    #[test]
+    fn test_collect_summary_time_splits() {
+        let path = "data/stationary_segment.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let options = SummaryOptions {
+            split_by: SplitBy::Time(4),
+            ..Default::default()
+        };
+        let summary = activity::collect_summary(&content, &options)
+            .unwrap()
+            .unwrap();
+        assert!(summary.split_time_summary.is_empty());
+        assert_eq!(
+            summary.time_split_summary,
+            vec![(4, 5.0), (8, 15.0), (9, 0.0)]
+        );
+    }
+
+    #[test]
+    fn test_collect_summary_smooth_hr() {
+        let path = "data/noisy_heart_rate.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+
+        let raw = activity::collect_summary(&content, &SummaryOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            raw.heart_rate_summary.details,
+            vec![("-150".to_owned(), 10u32), (">150".to_owned(), 10u32)]
+        );
+
+        let options = SummaryOptions {
+            smooth_hr: Some(4),
+            ..Default::default()
+        };
+        let smoothed = activity::collect_summary(&content, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            smoothed.heart_rate_summary.details,
+            vec![("-150".to_owned(), 20u32)]
+        );
+    }
+
+    #[test]
+    fn test_collect_summary_grade_adjusted_pace() {
+        let path = "data/steady_climb.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let summary = activity::collect_summary(&content, &SummaryOptions::default())
+            .unwrap()
+            .unwrap();
+        // 100 m at a 10% grade in 10 s costs as much as 165.8 m on the flat
+        let gap = summary.grade_adjusted_pace.unwrap();
+        assert!((gap - 60.32).abs() < 0.01, "{}", gap);
+
+        let path = "data/stationary_segment.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let summary = activity::collect_summary(&content, &SummaryOptions::default())
+            .unwrap()
+            .unwrap();
+        assert!(summary.grade_adjusted_pace.is_none());
+    }
+
+    #[test]
+    fn test_short_track_falls_back_to_activity_summary() {
+        let path = "data/short_track.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let options = SummaryOptions {
+            min_trackpoints: 10,
+            ..Default::default()
+        };
+        assert!(activity::collect_summary(&content, &options)
+            .unwrap()
+            .is_none());
+
+        let activity = Activity {
+            logId: 1,
+            activityName: "Run".to_owned(),
+            activityTypeId: 90009,
+            startTime: "2023-05-04T07:00:00.000+09:00".to_owned(),
+            distance: Some(5.2),
+            duration: 1800000,
+            activeDuration: Some(1700000),
+            calories: 320,
+            averageHeartRate: Some(142),
+            note: None,
+        };
+        let output = ActivityOutput::from_activity_log(&activity, &content, &options);
+        assert_eq!(output.distance, Some(5.2));
+        assert_eq!(output.active_duration, Some(1700000));
+        assert_eq!(output.heart_rate_average, 142);
+        assert!(output.split_times.is_empty());
+        assert!(output.heart_rate_details.is_empty());
+        assert_eq!(output.warnings, vec![Warning::TooFewTrackpoints]);
+    }
+
+    #[test]
+    fn test_api_version() {
+        assert_eq!(parse_api_version("1.2").unwrap(), "1.2");
+        assert!(parse_api_version("2").is_err());
+        let config = FitbitApiConfig {
+            base_url: "https://api.fitbit.com".to_owned(),
+            api_version: "1.2".to_owned(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            http: HttpConfig {
+                timeout: std::time::Duration::from_secs(1),
+                proxy: None,
+                user_agent: String::new(),
+            },
+            token_skew: Duration::zero(),
+            activity: ActivityMatcher::Name("Run".to_owned()),
+        };
+        assert_eq!(
+            config.user_url("activities/list.json"),
+            "https://api.fitbit.com/1.2/user/-/activities/list.json"
+        );
+    }
+
+    #[test]
+    fn test_activities_pagination() {
+        let json = r#"{"activities": [], "pagination": {"beforeDate": "2023-05-01T07:00:00",
+            "limit": 100, "next": "https://api.fitbit.com/1/user/-/activities/list.json?offset=100",
+            "offset": 0, "previous": "", "sort": "desc"}}"#;
+        let page = serde_json::from_str::<Activities>(json).unwrap();
+        assert!(page.pagination.next.ends_with("offset=100"));
+        let last = serde_json::from_str::<Activities>(r#"{"activities": []}"#).unwrap();
+        assert!(last.pagination.next.is_empty());
+    }
+
+    #[test]
+    fn test_daily_summary_response() {
+        let json = r#"{"activities": [], "summary": {"steps": 12340, "caloriesOut": 2400,
+            "distances": [{"activity": "tracker", "distance": 8.1}, {"activity": "total", "distance": 8.25}]}}"#;
+        let summary = serde_json::from_str::<DailySummaryResponse>(json)
+            .unwrap()
+            .summary;
+        assert_eq!(summary.steps, 12340);
+        let total = summary.distances.iter().find(|d| d.activity == "total");
+        assert_eq!(total.map(|d| d.distance), Some(8.25));
+    }
+
+    #[test]
+    fn test_activity_output_json_roundtrip() {
+        let path = "data/stationary_segment.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let output = summarize_tcx(&content, &SummaryOptions::default())
+            .unwrap()
+            .unwrap();
+        let json = serde_json::to_string(&output).unwrap();
+        let restored: ActivityOutput = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.start_time, output.start_time);
+        assert_eq!(restored.heart_rate_details, output.heart_rate_details);
+
+        let mismatch = serde_json::from_str::<ActivityOutput>(r#"{"distance": 5.0}"#);
+        assert!(mismatch.unwrap_err().to_string().contains("start_time"));
+    }
+
+    #[test]
+    fn test_summarize_tcx() {
+        let path = "data/stationary_segment.xml";
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("Failed to read from file: {}", path));
+        let output = summarize_tcx(&content, &SummaryOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(output.start_time, "2023-05-01T07:00:00+09:00");
+        assert_eq!(output.distance, Some(0.02));
+        assert_eq!(output.duration, 9000);
+        assert_eq!(output.calories, 0);
+    }
+
+    #[test]
+    fn test_heart_rate_warnings() {
+        let tcx = |heart_rates: [Option<u32>; 3]| {
+            let trackpoints = heart_rates
+                .iter()
+                .enumerate()
+                .map(|(i, hr)| {
+                    let hr = hr.map_or(String::new(), |hr| {
+                        format!("<HeartRateBpm><Value>{}</Value></HeartRateBpm>", hr)
+                    });
+                    format!(
+                        "<Trackpoint><Time>2023-05-01T07:00:0{}.000+09:00</Time>\
+                         <DistanceMeters>{}.0</DistanceMeters>{}</Trackpoint>",
+                        i,
+                        i * 3,
+                        hr
+                    )
+                })
+                .collect::<String>();
+            format!(
+                "<TrainingCenterDatabase><Activities><Activity><Id>1</Id><Lap><Track>{}\
+                 </Track></Lap></Activity></Activities></TrainingCenterDatabase>",
+                trackpoints
+            )
+        };
+        let summarize =
+            |heart_rates| summarize_tcx(&tcx(heart_rates), &SummaryOptions::default()).unwrap();
+
+        let gaps = summarize([None, Some(140), Some(150)]).unwrap();
+        assert_eq!(
+            gaps.warnings,
+            vec![Warning::HeartRateGaps { trackpoints: 1 }]
+        );
+        assert_eq!(gaps.heart_rate_average, 143);
+        assert_eq!(
+            gaps.warnings[0].to_string(),
+            "1 trackpoint has no heart rate, filled in from the last recorded one before them"
+        );
+
+        let missing = summarize([None, None, None]).unwrap();
+        assert_eq!(missing.warnings, vec![Warning::MissingHeartRate]);
+        assert!(missing.heart_rate_details.is_empty());
+
+        assert!(summarize([Some(140), Some(145), Some(150)])
+            .unwrap()
+            .warnings
+            .is_empty());
+    }
 }
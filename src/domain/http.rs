@@ -0,0 +1,173 @@
+use std::future::Future;
+use std::time::Duration;
+
+use reqwest::{Client, Error, Proxy, Result};
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub timeout: Duration,
+    /// Proxy for every request, see `parse_proxy`. Without one, reqwest still uses
+    /// `HTTP_PROXY`/`HTTPS_PROXY` from the environment.
+    pub proxy: Option<String>,
+    pub user_agent: String,
+}
+
+/// `running_tracker/<version>`, for when `HTTP_USER_AGENT` is not set.
+pub fn default_user_agent() -> String {
+    format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+impl HttpConfig {
+    pub fn client(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .timeout(self.timeout)
+            .user_agent(&self.user_agent);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        builder.build()
+    }
+}
+
+/// Checks an `http://`, `https://`, `socks5://` or `socks5h://` proxy URL.
+pub fn parse_proxy(url: &str) -> std::result::Result<String, String> {
+    Proxy::all(url)
+        .map(|_| url.to_owned())
+        .map_err(|e| format!("invalid proxy URL {}: {}", url, e))
+}
+
+/// Turns a request error into a message for the user.
+/// reqwest's own message for timeouts does not say which host hung, so spell it out.
+pub fn describe_error(e: &Error) -> String {
+    if e.is_timeout() {
+        let host = e
+            .url()
+            .and_then(|url| url.host_str())
+            .unwrap_or("unknown host");
+        format!("request to {} timed out", host)
+    } else {
+        e.to_string()
+    }
+}
+
+/// Whether a failed request may succeed when sent again: the connection failed or the
+/// server answered 5xx. A timeout only counts when `idempotent`, since the request may
+/// have gone through before the response was lost.
+pub fn is_transient(e: &Error, idempotent: bool) -> bool {
+    e.is_connect()
+        || e.status().is_some_and(|status| status.is_server_error())
+        || (idempotent && e.is_timeout())
+}
+
+/// Calls `request` until it succeeds, fails for good or `max_retries` retries are used up,
+/// waiting 1s, 2s, 4s, ... in between. Returns the last result and the number of attempts.
+pub async fn retry<T, F, Fut>(
+    max_retries: u32,
+    idempotent: bool,
+    mut request: F,
+) -> (Result<T>, u32)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match request().await {
+            Err(e) if attempts <= max_retries && is_transient(&e, idempotent) => {
+                tokio::time::sleep(Duration::from_secs(1 << (attempts - 1))).await;
+            }
+            result => return (result, attempts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_retry_counts_attempts() {
+        // nothing listens on a port just released, so every attempt fails to connect
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = Client::new();
+        let url = format!("http://127.0.0.1:{}/", port);
+        let (result, attempts) = retry(1, false, || client.get(&url).send()).await;
+        assert!(result.unwrap_err().is_connect());
+        assert_eq!(attempts, 2);
+    }
+
+    /// Answers one request with 200 and returns the address and the request as received.
+    fn mock_server() -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let n = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+        (address, server)
+    }
+
+    #[tokio::test]
+    async fn test_client_sends_user_agent() {
+        let (address, server) = mock_server();
+        let config = HttpConfig {
+            timeout: Duration::from_secs(5),
+            proxy: None,
+            user_agent: default_user_agent(),
+        };
+        config
+            .client()
+            .unwrap()
+            .get(format!("http://{}/", address))
+            .send()
+            .await
+            .unwrap();
+        let expected = format!(
+            "user-agent: running_tracker/{}\r\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(server.join().unwrap().contains(&expected));
+    }
+
+    #[tokio::test]
+    async fn test_client_sends_through_proxy() {
+        let (address, server) = mock_server();
+        let config = HttpConfig {
+            timeout: Duration::from_secs(5),
+            proxy: Some(format!("http://{}", address)),
+            user_agent: default_user_agent(),
+        };
+        let res = config
+            .client()
+            .unwrap()
+            .get("http://fitbit.invalid/1/user")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET http://fitbit.invalid/1/user HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_parse_proxy() {
+        assert!(parse_proxy("socks5://127.0.0.1:1080").is_ok());
+        assert!(parse_proxy("http://proxy example").is_err());
+    }
+}
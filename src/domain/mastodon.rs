@@ -1,27 +1,119 @@
-use reqwest::{Client, Result};
+use chrono::{DateTime, FixedOffset};
+use reqwest::{Client, Result, StatusCode};
+use serde::Deserialize;
+
+use super::media::Photo;
 
 #[derive(Debug)]
 pub struct MastodonApiConfig {
     pub base_url: String,
     pub token: String,
+    /// Built once for a post and shared by its requests, retries included.
+    pub client: Client,
+}
+
+/// A status, a scheduled status and an uploaded media all respond with an `id`.
+#[derive(Deserialize, Debug)]
+struct StatusResponse {
+    id: String,
+}
+
+/// A posted or edited status.
+#[derive(Deserialize, Debug)]
+pub struct Status {
+    pub id: String,
+    /// Web page of the status; a scheduled status has none yet.
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// Maximum number of media attachments of a status.
+pub const MEDIA_LIMIT: usize = 4;
+
+/// Uploads an image and returns the media id to attach it with.
+pub async fn upload_media(config: &MastodonApiConfig, photo: &Photo) -> Result<String> {
+    let url = format!("{}/media", config.base_url);
+    let part = reqwest::multipart::Part::bytes(photo.bytes.clone())
+        .file_name(photo.file_name.clone())
+        .mime_str(photo.mime)?;
+    let form = reqwest::multipart::Form::new().part("file", part);
+    let res = config
+        .client
+        .post(&url)
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", config.token),
+        )
+        .multipart(form)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(res.json::<StatusResponse>().await?.id)
 }
 
-pub async fn post(config: &MastodonApiConfig, text: &String) -> Result<()> {
+/// Posts a status, or schedules it when `scheduled_at` is given.
+/// The instance answers a repeated `idempotency_key` with the status it already made,
+/// so the post can be retried safely.
+pub async fn post(
+    config: &MastodonApiConfig,
+    text: &String,
+    scheduled_at: Option<&DateTime<FixedOffset>>,
+    media_ids: &[String],
+    idempotency_key: &str,
+) -> Result<Status> {
     let url = format!("{}/statuses", config.base_url);
-    let req_form = [("status", text)];
-    let res = Client::new()
+    let mut req_form = vec![("status", text.to_owned())];
+    if let Some(scheduled_at) = scheduled_at {
+        req_form.push(("scheduled_at", scheduled_at.to_rfc3339()));
+    }
+    for media_id in media_ids {
+        req_form.push(("media_ids[]", media_id.to_owned()));
+    }
+    let res = config
+        .client
         .post(&url)
         .header(
             reqwest::header::AUTHORIZATION,
             format!("Bearer {}", config.token),
         )
+        .header("Idempotency-Key", idempotency_key)
+        .form(&req_form)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    res.json::<Status>().await
+}
+
+/// Answers to an edit that a new status should stand in for: the status no longer exists,
+/// or the instance predates editing.
+const EDIT_UNSUPPORTED: [StatusCode; 3] = [
+    StatusCode::NOT_FOUND,
+    StatusCode::METHOD_NOT_ALLOWED,
+    StatusCode::NOT_IMPLEMENTED,
+];
+
+/// Replaces the text of a status, or returns `None` if the status no longer exists or
+/// cannot be edited. Fails on any other error.
+pub async fn edit(config: &MastodonApiConfig, id: &str, text: &String) -> Result<Option<Status>> {
+    let url = format!("{}/statuses/{}", config.base_url, id);
+    let req_form = [("status", text)];
+    let res = config
+        .client
+        .put(&url)
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", config.token),
+        )
         .form(&req_form)
         .send()
         .await?;
 
-    if !res.status().is_success() {
-        panic!("Post failed.");
+    if EDIT_UNSUPPORTED.contains(&res.status()) {
+        return Ok(None);
     }
+    let res = res.error_for_status()?;
 
-    Ok(())
+    Ok(Some(res.json::<Status>().await?))
 }
@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::Path;
+
+/// An image read from disk to attach to a post.
+#[derive(Debug, Clone)]
+pub struct Photo {
+    pub file_name: String,
+    pub mime: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// Image type from the leading bytes of the file.
+fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+pub fn load(path: &Path) -> Result<Photo, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mime = sniff_mime(&bytes)
+        .ok_or_else(|| format!("{}: not a PNG, JPEG, GIF or WebP image", path.display()))?;
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "photo".to_owned());
+    Ok(Photo {
+        file_name,
+        mime,
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sniff_mime() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n"), Some("image/png"));
+        assert_eq!(sniff_mime(b"\xFF\xD8\xFF\xE0"), Some("image/jpeg"));
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_mime(b"<?xml"), None);
+    }
+}
@@ -1,22 +1,122 @@
-use reqwest::{Client, Result};
+use reqwest::{Client, Result, StatusCode};
+use serde::Deserialize;
+
+use super::media::Photo;
 
 #[derive(Debug)]
 pub struct MisskeyApiConfig {
     pub base_url: String,
     pub token: String,
+    pub client: Client,
+}
+
+#[derive(Deserialize, Debug)]
+struct DriveFile {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Note {
+    id: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct CreateNoteResponse {
+    createdNote: Note,
 }
 
-pub async fn post(config: &MisskeyApiConfig, text: &String) -> Result<()> {
+/// Maximum number of files attached to a note.
+pub const MEDIA_LIMIT: usize = 16;
+
+/// Uploads an image to the drive and returns the file id to attach it with.
+pub async fn upload_media(config: &MisskeyApiConfig, photo: &Photo) -> Result<String> {
+    let url = format!("{}/drive/files/create", &config.base_url);
+    let part = reqwest::multipart::Part::bytes(photo.bytes.clone())
+        .file_name(photo.file_name.clone())
+        .mime_str(photo.mime)?;
+    let form = reqwest::multipart::Form::new()
+        .text("i", config.token.to_owned())
+        .part("file", part);
+    let res = config
+        .client
+        .post(&url)
+        .multipart(form)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(res.json::<DriveFile>().await?.id)
+}
+
+/// Posts a note and returns its id.
+pub async fn post(config: &MisskeyApiConfig, text: &String, file_ids: &[String]) -> Result<String> {
     let url = format!("{}/notes/create", &config.base_url);
+    let mut req_json = serde_json::json!({
+        "text": text,
+        "i": &config.token,
+    });
+    if !file_ids.is_empty() {
+        req_json["fileIds"] = serde_json::json!(file_ids);
+    }
+    let res = config
+        .client
+        .post(&url)
+        .json(&req_json)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(res.json::<CreateNoteResponse>().await?.createdNote.id)
+}
+
+/// Answers to `notes/update` where the instance does not have it.
+const EDIT_UNSUPPORTED: [StatusCode; 3] = [
+    StatusCode::NOT_FOUND,
+    StatusCode::METHOD_NOT_ALLOWED,
+    StatusCode::NOT_IMPLEMENTED,
+];
+
+/// Web page of the note: `notes/<id>` on the host of the API.
+pub fn note_url(config: &MisskeyApiConfig, id: &str) -> Option<String> {
+    let base = reqwest::Url::parse(&config.base_url).ok()?;
+    base.join(&format!("/notes/{}", id)).ok().map(String::from)
+}
+
+/// Replaces the text of a note and returns its id.
+/// `notes/update` only exists on some instances; returns `None` where it does not,
+/// and fails on any other error.
+pub async fn edit(config: &MisskeyApiConfig, id: &str, text: &String) -> Result<Option<String>> {
+    let url = format!("{}/notes/update", &config.base_url);
     let req_json = serde_json::json!({
+        "noteId": id,
         "text": text,
         "i": &config.token,
     });
-    let res = Client::new().post(&url).json(&req_json).send().await?;
+    let res = config.client.post(&url).json(&req_json).send().await?;
 
-    if !res.status().is_success() {
-        panic!("Post failed.");
+    if EDIT_UNSUPPORTED.contains(&res.status()) {
+        return Ok(None);
     }
+    res.error_for_status()?;
+
+    Ok(Some(id.to_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    Ok(())
+    #[test]
+    fn test_note_url() {
+        let config = MisskeyApiConfig {
+            base_url: "https://misskey.example/api".to_owned(),
+            token: String::new(),
+            client: Client::new(),
+        };
+        assert_eq!(
+            note_url(&config, "9abc").as_deref(),
+            Some("https://misskey.example/notes/9abc")
+        );
+    }
 }
@@ -1,4 +1,12 @@
+pub mod condition;
 pub mod fitbit;
+pub mod http;
 pub mod mastodon;
+pub mod media;
 pub mod misskey;
+pub mod outbox;
+pub mod state;
+pub mod summary;
+pub mod totals;
 pub mod view;
+pub mod webhook;
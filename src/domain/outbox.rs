@@ -0,0 +1,181 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use super::state::LastPost;
+
+const OUTBOX_FILE_PATH: &str = "outbox.jsonl";
+/// How often, and how long apart, to try to take a lock another process holds.
+const LOCK_ATTEMPTS: u32 = 50;
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A post that failed transiently and is kept for `flush-outbox`. One JSON object per line.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Entry {
+    pub platform: String,
+    /// Named account, `None` for the one of the unprefixed variables.
+    #[serde(default)]
+    pub account: Option<String>,
+    pub text: String,
+    #[serde(default)]
+    pub scheduled_at: Option<DateTime<FixedOffset>>,
+    /// The key of the first attempt, so a post that did go through is not made twice.
+    pub idempotency_key: String,
+    /// The run the post reports, recorded as the last post once it is flushed;
+    /// `None` for a summary.
+    #[serde(default)]
+    pub last_post: Option<LastPost>,
+    /// Distance (km) to add to the lifetime total once flushed, with `--lifetime`.
+    #[serde(default)]
+    pub lifetime_distance: Option<f64>,
+}
+
+/// Entries in the order they were queued. Lines that cannot be read are reported and skipped,
+/// and left in the file by `remove`.
+pub fn load() -> Vec<Entry> {
+    load_from(OUTBOX_FILE_PATH)
+}
+
+fn load_from(path: &str) -> Vec<Entry> {
+    let file = match OpenOptions::new().read(true).open(Path::new(path)) {
+        Err(_) => return vec![],
+        Ok(file) => file,
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                eprintln!("Warning: skipping an invalid line of {}. {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn append(entry: &Entry) -> io::Result<()> {
+    append_to(OUTBOX_FILE_PATH, entry)
+}
+
+fn append_to(path: &str, entry: &Entry) -> io::Result<()> {
+    let _lock = lock(path)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry).unwrap())
+}
+
+/// Removes the entries with the keys, those posted by `flush-outbox`, keeping every other
+/// line as it is, along with the ones queued meanwhile. Removes the file when nothing is left.
+/// Written to a temporary file first and renamed, like totals.json.
+pub fn remove(idempotency_keys: &[String]) -> io::Result<()> {
+    remove_from(OUTBOX_FILE_PATH, idempotency_keys)
+}
+
+fn remove_from(path: &str, idempotency_keys: &[String]) -> io::Result<()> {
+    let _lock = lock(path)?;
+    let content = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        result => result?,
+    };
+    let kept = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => !idempotency_keys.contains(&entry.idempotency_key),
+            Err(_) => true,
+        })
+        .collect::<Vec<&str>>();
+    if kept.is_empty() {
+        return fs::remove_file(path);
+    }
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path)?;
+    for line in kept {
+        writeln!(file, "{}", line)?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Held while the outbox is written, so that a post queued while `flush-outbox` rewrites
+/// the file is not lost. The lock file is removed when dropped.
+struct Lock {
+    path: String,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn lock(path: &str) -> io::Result<Lock> {
+    let lock_path = format!("{}.lock", path);
+    for _ in 0..LOCK_ATTEMPTS {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(_) => return Ok(Lock { path: lock_path }),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                thread::sleep(LOCK_RETRY_INTERVAL)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::WouldBlock,
+        format!(
+            "{} is held by another process, remove it if none is running",
+            lock_path
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_append_and_remove() {
+        let path = &format!(
+            "{}/outbox-test-{}.jsonl",
+            std::env::temp_dir().display(),
+            std::process::id()
+        );
+        let entry = |text: &str| Entry {
+            platform: "mastodon".to_owned(),
+            account: None,
+            text: text.to_owned(),
+            scheduled_at: None,
+            idempotency_key: format!("key-{}", text),
+            last_post: None,
+            lifetime_distance: None,
+        };
+        append_to(path, &entry("first")).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "not json"))
+            .unwrap();
+        append_to(path, &entry("second")).unwrap();
+        assert_eq!(load_from(path), vec![entry("first"), entry("second")]);
+
+        remove_from(path, &["key-first".to_owned()]).unwrap();
+        assert_eq!(load_from(path), vec![entry("second")]);
+        remove_from(path, &["key-second".to_owned()]).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "not json\n");
+        assert!(!Path::new(&format!("{}.lock", path)).exists());
+
+        fs::write(path, "").unwrap();
+        remove_from(path, &[]).unwrap();
+        assert!(!Path::new(path).exists());
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+const STATE_FILE_PATH: &str = "state.json";
+
+/// The run of the last successful post.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LastPost {
+    /// `None` for a run read from a file.
+    pub log_id: Option<u64>,
+    /// RFC 3339
+    pub start_time: String,
+}
+
+/// What is kept between runs, apart from the Fitbit credentials.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    /// Id of the post `--edit-latest` edits, by platform name.
+    #[serde(default)]
+    pub latest_posts: HashMap<String, String>,
+    /// What `--since-last-post` continues from.
+    #[serde(default)]
+    pub last_post: Option<LastPost>,
+}
+
+/// The stored state, the default one before anything is stored. Fails when state.json
+/// cannot be read, rather than starting over and losing it on the next store.
+pub fn load() -> Result<State, serde_json::Error> {
+    let path = Path::new(STATE_FILE_PATH);
+    let file = match OpenOptions::new().read(true).open(path) {
+        Err(_) => return Ok(State::default()),
+        Ok(file) => file,
+    };
+    serde_json::from_reader(file)
+}
+
+/// Written to a temporary file first and renamed, like totals.json.
+pub fn store(state: &State) {
+    let tmp_path = format!("{}.tmp", STATE_FILE_PATH);
+    let mut file = File::create(&tmp_path).expect("Failed to create state.json.tmp.");
+    file.write_all(serde_json::to_string_pretty(state).unwrap().as_bytes())
+        .expect("Failed to write to state.json.tmp.");
+    file.sync_all().expect("Failed to write to state.json.tmp.");
+    fs::rename(&tmp_path, STATE_FILE_PATH).expect("Failed to replace state.json.");
+}
@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Weekday};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use super::fitbit::Activity;
+use super::view::NumberFormat;
+
+#[derive(Clone, Copy, ValueEnum, Debug, PartialEq)]
+pub enum Period {
+    /// 7 days from --since
+    Weekly,
+    /// a month from --since
+    Monthly,
+}
+
+impl Period {
+    /// Last day of the period starting on `start`.
+    pub fn end(&self, start: NaiveDate) -> NaiveDate {
+        let next = match self {
+            Period::Weekly => start + Duration::days(7),
+            Period::Monthly => start.checked_add_months(Months::new(1)).unwrap(),
+        };
+        next.pred_opt().unwrap()
+    }
+}
+
+/// Resolves `--since`: a date as YYYY-MM-DD, `week` for the latest `week_start` day up to
+/// today or `month` for the first day of the current month.
+pub fn parse_since(s: &str, today: NaiveDate, week_start: Weekday) -> Result<NaiveDate, String> {
+    match s {
+        "week" => {
+            let days = (7 + today.weekday().num_days_from_monday()
+                - week_start.num_days_from_monday())
+                % 7;
+            Ok(today - Duration::days(days as i64))
+        }
+        "month" => Ok(today.with_day(1).unwrap()),
+        _ => NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map_err(|_| format!("since must be YYYY-MM-DD, week or month: {}", s)),
+    }
+}
+
+pub fn parse_weekday(s: &str) -> Result<Weekday, String> {
+    s.parse::<Weekday>()
+        .map_err(|_| format!("not a day of the week: {}", s))
+}
+
+/// What the summary template sees.
+#[derive(Serialize, Debug)]
+pub struct SummaryViewModel {
+    start_date: String,
+    end_date: String,
+    runs: usize,
+    distance: String,
+    longest: String,
+    /// Calendar rows from Monday to Sunday, see `heatmap`.
+    heatmap: Vec<String>,
+}
+
+/// Total distance (km) of the runs of each day.
+pub fn daily_distances(activities: &[Activity]) -> BTreeMap<NaiveDate, f32> {
+    let mut days = BTreeMap::new();
+    for activity in activities {
+        let Ok(start_time) = DateTime::parse_from_rfc3339(&activity.startTime) else {
+            continue;
+        };
+        *days.entry(start_time.date_naive()).or_insert(0.0) += activity.distance.unwrap_or(0.0);
+    }
+    days
+}
+
+const HEAT_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One row per calendar week from Monday to Sunday, one character per day: a block as
+/// high as the distance relative to the longest day, `·` for a day without a run and a
+/// space for a day outside `start..=end`.
+pub fn heatmap(start: NaiveDate, end: NaiveDate, days: &BTreeMap<NaiveDate, f32>) -> Vec<String> {
+    let max = days.range(start..=end).map(|(_, d)| *d).fold(0.0, f32::max);
+    let top = (HEAT_BLOCKS.len() - 1) as f32;
+    let mut day = start - Duration::days(start.weekday().num_days_from_monday() as i64);
+    let mut rows = vec![];
+    while day <= end {
+        let row = (0..7)
+            .map(|i| {
+                let date = day + Duration::days(i);
+                if date < start || date > end {
+                    return ' ';
+                }
+                match days.get(&date) {
+                    Some(distance) if *distance > 0.0 && max > 0.0 => {
+                        HEAT_BLOCKS[(distance / max * top).round() as usize]
+                    }
+                    _ => '·',
+                }
+            })
+            .collect();
+        rows.push(row);
+        day += Duration::days(7);
+    }
+    rows
+}
+
+impl SummaryViewModel {
+    /// Summarizes the runs among `activities` started within `start..=end`.
+    pub fn new(
+        start: NaiveDate,
+        end: NaiveDate,
+        activities: &[Activity],
+        number: &NumberFormat,
+    ) -> Self {
+        let days = &daily_distances(activities);
+        let in_period = || days.range(start..=end).map(|(_, d)| *d);
+        let runs = activities
+            .iter()
+            .filter_map(|a| DateTime::parse_from_rfc3339(&a.startTime).ok())
+            .filter(|t| (start..=end).contains(&t.date_naive()))
+            .count();
+        Self {
+            start_date: start.format("%Y-%m-%d").to_string(),
+            end_date: end.format("%Y-%m-%d").to_string(),
+            runs,
+            distance: number.number(in_period().sum::<f32>() as f64, 1),
+            longest: number.number(in_period().fold(0.0, f32::max) as f64, 1),
+            heatmap: heatmap(start, end, days),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::view;
+    use super::*;
+
+    #[test]
+    fn test_heatmap() {
+        let date = |d| NaiveDate::from_ymd_opt(2023, 5, d).unwrap();
+        // 2023-05-01 is a Monday
+        let days = BTreeMap::from([(date(1), 5.0), (date(3), 10.0), (date(6), 2.5)]);
+        assert_eq!(heatmap(date(1), date(7), &days), vec!["▅·█··▃·"]);
+        assert_eq!(
+            heatmap(date(3), Period::Weekly.end(date(3)), &days),
+            vec!["  █··▃·", "··     "]
+        );
+    }
+
+    #[test]
+    fn test_summary_counts_runs() {
+        let date = |d| NaiveDate::from_ymd_opt(2023, 5, d).unwrap();
+        let activity = |start_time: &str, distance: f32| {
+            serde_json::from_value::<Activity>(serde_json::json!({
+                "logId": 1,
+                "activityName": "Run",
+                "activityTypeId": 90009,
+                "startTime": start_time,
+                "distance": distance,
+                "duration": 1800000,
+                "calories": 300,
+            }))
+            .unwrap()
+        };
+        let activities = [
+            activity("2023-05-01T07:00:00+09:00", 5.0),
+            activity("2023-05-01T19:00:00+09:00", 3.0),
+            activity("2023-05-08T07:00:00+09:00", 10.0),
+        ];
+        let summary =
+            SummaryViewModel::new(date(1), date(7), &activities, &NumberFormat::default());
+        assert_eq!(summary.runs, 2);
+        assert_eq!(summary.distance, "8.0");
+
+        let render = |summary: &SummaryViewModel| {
+            view::get(
+                summary,
+                view::SUMMARY_TEMPLATE,
+                &view::ViewOptions::default(),
+            )
+            .unwrap()
+        };
+        assert!(render(&summary).contains("\n2 runs, 8.0 km\n"));
+        let single =
+            SummaryViewModel::new(date(8), date(14), &activities, &NumberFormat::default());
+        assert!(render(&single).contains("\n1 run, 10.0 km\n"));
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let date = |m, d| NaiveDate::from_ymd_opt(2023, m, d).unwrap();
+        // 2023-05-10 is a Wednesday
+        let today = date(5, 10);
+        assert_eq!(parse_since("week", today, Weekday::Mon), Ok(date(5, 8)));
+        assert_eq!(parse_since("week", today, Weekday::Sun), Ok(date(5, 7)));
+        assert_eq!(parse_since("week", today, Weekday::Wed), Ok(today));
+        assert_eq!(parse_since("month", today, Weekday::Mon), Ok(date(5, 1)));
+        assert_eq!(
+            parse_since("2023-04-02", today, Weekday::Mon),
+            Ok(date(4, 2))
+        );
+        assert!(parse_since("yesterday", today, Weekday::Mon).is_err());
+    }
+
+    #[test]
+    fn test_period_end() {
+        let date = |m, d| NaiveDate::from_ymd_opt(2023, m, d).unwrap();
+        assert_eq!(Period::Weekly.end(date(5, 1)), date(5, 7));
+        assert_eq!(Period::Monthly.end(date(5, 1)), date(5, 31));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+const TOTALS_FILE_PATH: &str = "totals.json";
+
+/// Lifetime distance of the posted runs, kept for `--lifetime`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Totals {
+    pub distance: f64,
+    /// Fitbit log ids of the runs counted in `distance`, so a run is never counted twice.
+    #[serde(default)]
+    pub log_ids: BTreeSet<u64>,
+}
+
+impl Totals {
+    /// Adds the run unless it is already counted. Returns whether it was added.
+    pub fn add(&mut self, log_id: u64, distance: f64) -> bool {
+        if !self.log_ids.insert(log_id) {
+            return false;
+        }
+        self.distance += distance;
+        true
+    }
+
+    /// The total as it would be with the run added.
+    pub fn with(&self, log_id: Option<u64>, distance: f64) -> f64 {
+        match log_id {
+            Some(log_id) if !self.log_ids.contains(&log_id) => self.distance + distance,
+            _ => self.distance,
+        }
+    }
+}
+
+/// The stored total, 0 km before anything is stored. Fails when totals.json cannot be
+/// read, so that storing does not replace the total with the runs of this one.
+pub fn load() -> Result<Totals> {
+    let path = Path::new(TOTALS_FILE_PATH);
+    let file = match OpenOptions::new().read(true).open(path) {
+        Err(_) => return Ok(Totals::default()),
+        Ok(file) => file,
+    };
+    serde_json::from_reader(file).context("Failed to read totals.json, fix or remove it")
+}
+
+/// Writes to a temporary file first and renames it over totals.json, so an interrupted
+/// write never leaves a truncated total behind.
+pub fn store(totals: &Totals) {
+    let tmp_path = format!("{}.tmp", TOTALS_FILE_PATH);
+    let mut file = File::create(&tmp_path).expect("Failed to create totals.json.tmp.");
+    file.write_all(serde_json::to_string_pretty(totals).unwrap().as_bytes())
+        .expect("Failed to write to totals.json.tmp.");
+    file.sync_all()
+        .expect("Failed to write to totals.json.tmp.");
+    fs::rename(&tmp_path, TOTALS_FILE_PATH).expect("Failed to replace totals.json.");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_counts_each_log_once() {
+        let mut totals = Totals::default();
+        assert!(totals.add(1, 5.0));
+        assert!(!totals.add(1, 5.0));
+        assert!(totals.add(2, 10.0));
+        assert_eq!(totals.distance, 15.0);
+        assert_eq!(totals.with(Some(2), 10.0), 15.0);
+        assert_eq!(totals.with(Some(3), 1.0), 16.0);
+    }
+
+    #[test]
+    fn test_with_adds_what_add_would() {
+        let mut totals = Totals::default();
+        totals.add(1, 5.0);
+        for (log_id, distance) in [(Some(2), 10.0), (Some(1), 5.0), (None, 3.0)] {
+            let shown = totals.with(log_id, distance);
+            let mut added = Totals {
+                distance: totals.distance,
+                log_ids: totals.log_ids.clone(),
+            };
+            if let Some(log_id) = log_id {
+                added.add(log_id, distance);
+            }
+            assert_eq!(shown, added.distance);
+        }
+        assert_eq!(totals.with(None, 3.0), 5.0);
+    }
+}
@@ -1,51 +1,395 @@
 use std::error::Error;
+use std::{fs, io};
 
 use crate::fitbit;
 use chrono::DateTime;
-use handlebars::{Handlebars, RenderError};
+use clap::ValueEnum;
+use handlebars::{Handlebars, RenderError, Renderable};
 use serde::Serialize;
 
+/// How fractional seconds are dropped when pace is shown as `M:SS`.
+#[derive(Clone, Copy, ValueEnum, Debug, Default)]
+pub enum PaceRounding {
+    Floor,
+    #[default]
+    Round,
+    Ceil,
+}
+
+/// What each distance split is labeled with.
+#[derive(Clone, Copy, ValueEnum, Debug, Default)]
+pub enum SplitLabel {
+    /// `km 1`, `km 2`, ...
+    #[default]
+    Km,
+    /// `Lap 1`, `Lap 2`, ...
+    Lap,
+    /// `1 km`, `2 km`, ... the distance at the end of the split.
+    Cumulative,
+}
+
+impl SplitLabel {
+    fn label(&self, n: usize) -> String {
+        match self {
+            SplitLabel::Km => format!("km {}", n),
+            SplitLabel::Lap => format!("Lap {}", n),
+            SplitLabel::Cumulative => format!("{} km", n),
+        }
+    }
+}
+
+/// Which Fitbit duration the duration and pace are shown from.
+#[derive(Clone, Copy, ValueEnum, Debug, Default)]
+pub enum DurationSource {
+    /// `activeDuration`, excluding pauses, when Fitbit reports it; `duration` otherwise.
+    #[default]
+    Active,
+    /// `duration`, including pauses.
+    Total,
+}
+
+impl DurationSource {
+    /// Milliseconds.
+    pub fn duration(&self, output: &fitbit::ActivityOutput) -> u32 {
+        match self {
+            DurationSource::Active => output.active_duration.unwrap_or(output.duration),
+            DurationSource::Total => output.duration,
+        }
+    }
+}
+
+/// Markup flavor of the rendered text. Templates mark monospaced parts with `{{#mono}}`.
+#[derive(Clone, Copy, ValueEnum, Debug, Default, PartialEq)]
+pub enum Markup {
+    #[default]
+    Plain,
+    /// `{{#mono}}` becomes a code block, e.g. for Discord or Slack.
+    Markdown,
+    /// `{{#mono}}` becomes `<pre>` and other line breaks `<br>`.
+    Html,
+}
+
+/// Decimal separator and thousands grouping of the formatted numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    /// `None` leaves numbers ungrouped, except for the always grouped totals.
+    pub grouping: Option<char>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            decimal: '.',
+            grouping: None,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Rounded to `decimals` places, e.g. `1.234,5` for `de`.
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let formatted = format!("{:.*}", decimals, value);
+        let (whole, fraction) = match formatted.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+        let mut number = group_digits(whole, self.grouping);
+        if let Some(fraction) = fraction {
+            number.push(self.decimal);
+            number.push_str(fraction);
+        }
+        number
+    }
+
+    /// Rounded to a whole number and grouped, with `,` unless the locale groups otherwise.
+    pub fn grouped(&self, value: f64) -> String {
+        group_digits(&format!("{:.0}", value), Some(self.grouping.unwrap_or(',')))
+    }
+}
+
+fn group_digits(whole: &str, grouping: Option<char>) -> String {
+    let Some(separator) = grouping else {
+        return whole.to_owned();
+    };
+    let (sign, digits) = match whole.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", whole),
+    };
+    let mut formatted = sign.to_owned();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            formatted.push(separator);
+        }
+        formatted.push(c);
+    }
+    formatted
+}
+
+/// Parses a locale such as `de`, `fr_FR` or `en-US` by its language.
+pub fn parse_locale(s: &str) -> Result<NumberFormat, String> {
+    let language = s
+        .split(['_', '-', '.'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let (decimal, grouping) = match language.as_str() {
+        "c" | "posix" => ('.', None),
+        "en" | "ja" | "zh" | "ko" => ('.', Some(',')),
+        "de" | "es" | "it" | "nl" | "pt" | "id" | "da" | "tr" => (',', Some('.')),
+        // narrow no-break space
+        "fr" | "ru" | "pl" | "cs" | "sv" | "fi" | "nb" | "uk" => (',', Some('\u{202f}')),
+        _ => return Err(format!("unsupported locale: {}", s)),
+    };
+    Ok(NumberFormat { decimal, grouping })
+}
+
+#[derive(Debug, Default)]
+pub struct ViewOptions {
+    pub pace_rounding: PaceRounding,
+    pub duration_source: DurationSource,
+    pub number_format: NumberFormat,
+    pub markup: Markup,
+    pub split_label: SplitLabel,
+    /// Target pace in seconds per km to compare each split against.
+    pub target_pace: Option<u32>,
+    /// Resting and max heart rate to express heart rates as % of the reserve.
+    pub resting_hr: Option<u32>,
+    pub max_hr: Option<u32>,
+}
+
 #[derive(Serialize)]
-struct ActivityViewModel {
+struct CheckpointViewModel {
+    distance: String,
+    time: String,
+}
+
+#[derive(Serialize)]
+struct SplitViewModel {
+    label: String,
+    time: String,
+    /// Seconds ahead (`-`) or behind (`+`) the target pace.
+    delta: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TimeSplitViewModel {
+    /// Elapsed time at the end of the segment.
+    time: String,
+    distance: String,
+}
+
+/// What the templates of a single run see.
+#[derive(Serialize)]
+pub struct ActivityViewModel {
     start_time: String,
     distance: String,
+    /// Rounded to 0.1 km, for short templates.
+    distance_rounded: String,
+    /// `H:MM:SS`, or `M:SS` under an hour.
+    elapsed: String,
     duration_in_min: String,
     duration_per_km: String,
+    pace: String,
+    grade_adjusted_pace: Option<String>,
     split_times: Vec<String>,
-    calories: u32,
+    splits: Vec<SplitViewModel>,
+    checkpoints: Vec<CheckpointViewModel>,
+    time_splits: Vec<TimeSplitViewModel>,
+    calories: String,
     heart_rate_average: u32,
     heart_rate_max: u32,
+    heart_rate_reserve_average: Option<u32>,
+    heart_rate_reserve_max: Option<u32>,
+    trimp: Option<String>,
     heart_rate_zone_min_pairs: Vec<(String, u32)>,
+    zone_sparkline: String,
+    note: Option<String>,
+    history: Vec<String>,
+    daily_steps: Option<String>,
+    daily_distance: Option<String>,
+    lifetime: Option<String>,
+    week_total: Option<String>,
+    /// Trackpoint distance and its difference from `distance`, e.g. `+3.2%`, only when they differ.
+    gps_distance: Option<String>,
+    distance_discrepancy: Option<String>,
 }
 
 impl ActivityViewModel {
-    fn from_output(output: fitbit::ActivityOutput) -> Self {
+    /// `None` when the run has no distance, as there is nothing to report then.
+    pub fn from_output(output: &fitbit::ActivityOutput, options: &ViewOptions) -> Option<Self> {
+        let distance = output.distance?;
         let start_time = DateTime::parse_from_rfc3339(&output.start_time)
             .unwrap()
             .format("%Y-%m-%d")
             .to_string();
-        let distance = output.distance.unwrap();
-        let duration = output.duration as f32 / 60.0 / 1000.0;
+        let duration_ms = options.duration_source.duration(output);
+        let duration = duration_ms as f32 / 60.0 / 1000.0;
+        let number = &options.number_format;
+        // differences that round to 0.0% are not worth a line
+        let discrepancy = output
+            .distance_discrepancy()
+            .filter(|percent| (percent * 10.0).round() != 0.0);
 
-        Self {
+        Some(Self {
             start_time,
-            distance: format!("{:.1$}", distance, 3),
-            duration_in_min: format!("{:.1$}", duration, 3),
-            duration_per_km: format!("{:.1$}", duration / distance, 3),
-            split_times: output.split_times,
-            calories: output.calories,
+            distance: number.number(distance as f64, 3),
+            distance_rounded: number.number(distance as f64, 1),
+            elapsed: format_elapsed((duration_ms as f64 / 1000.0).round() as u32),
+            duration_in_min: number.number(duration as f64, 3),
+            duration_per_km: number.number((duration / distance) as f64, 3),
+            pace: format_pace(
+                duration_ms as f64 / 1000.0 / distance as f64,
+                options.pace_rounding,
+            ),
+            grade_adjusted_pace: output
+                .grade_adjusted_pace
+                .map(|pace| format_pace(pace, options.pace_rounding)),
+            split_times: output.split_times.clone(),
+            // splits are recorded at every full km only, so each one is comparable to the target
+            splits: output
+                .split_times
+                .iter()
+                .zip(&output.split_seconds)
+                .enumerate()
+                .map(|(i, (time, seconds))| SplitViewModel {
+                    label: options.split_label.label(i + 1),
+                    time: time.to_owned(),
+                    delta: options
+                        .target_pace
+                        .map(|target| format!("{:+}s", *seconds as i64 - target as i64)),
+                })
+                .collect(),
+            checkpoints: output
+                .checkpoints
+                .iter()
+                .map(|(distance, seconds)| CheckpointViewModel {
+                    // markers such as 21.0975 keep all their digits
+                    distance: distance
+                        .to_string()
+                        .replace('.', &number.decimal.to_string()),
+                    time: format_elapsed(*seconds),
+                })
+                .collect(),
+            time_splits: output
+                .time_splits
+                .iter()
+                .map(|(seconds, distance)| TimeSplitViewModel {
+                    time: format_elapsed(*seconds),
+                    distance: number.number(*distance, 2),
+                })
+                .collect(),
+            calories: number.number(output.calories as f64, 0),
             heart_rate_average: output.heart_rate_average,
             heart_rate_max: output.heart_rate_max,
+            heart_rate_reserve_average: percent_of_reserve(output.heart_rate_average, options),
+            heart_rate_reserve_max: percent_of_reserve(output.heart_rate_max, options),
+            trimp: output.trimp.map(|trimp| number.number(trimp, 0)),
             heart_rate_zone_min_pairs: output
                 .heart_rate_details
                 .iter()
                 .map(|(range, value)| (range.to_owned(), value / 60u32))
                 .collect(),
-        }
+            zone_sparkline: zone_sparkline(&output.heart_rate_details),
+            note: output.note.clone(),
+            history: output
+                .history
+                .iter()
+                .map(|distance| number.number(*distance as f64, 1))
+                .collect(),
+            daily_steps: output.daily_steps.map(|steps| number.grouped(steps as f64)),
+            daily_distance: output
+                .daily_distance
+                .map(|distance| number.number(distance as f64, 2)),
+            lifetime: output
+                .lifetime_distance
+                .map(|distance| number.grouped(distance)),
+            week_total: output
+                .week_distance
+                .map(|distance| number.number(distance, 1)),
+            gps_distance: discrepancy
+                .and(output.gps_distance)
+                .map(|distance| number.number(distance as f64, 3)),
+            distance_discrepancy: discrepancy.map(|percent| {
+                let sign = if percent > 0.0 { "+" } else { "" };
+                format!("{}{}%", sign, number.number(percent, 1))
+            }),
+        })
     }
 }
 
 const TEMPLATE_PATH: &str = "./templates";
+/// Template of `--summary`, rendered from a summary view model instead of a run.
+pub const SUMMARY_TEMPLATE: &str = "summary";
+
+const SPARK_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One block per heart rate zone, from the lowest, scaled to the zone with the most time.
+/// Empty when there is no heart rate data.
+fn zone_sparkline(heart_rate_details: &[(String, u32)]) -> String {
+    let seconds = fitbit::HEART_RATE_ZONES
+        .iter()
+        .map(|zone| {
+            heart_rate_details
+                .iter()
+                .find(|(range, _)| range == zone)
+                .map_or(0, |(_, seconds)| *seconds)
+        })
+        .collect::<Vec<u32>>();
+    let max = seconds.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return String::new();
+    }
+    let top = (SPARK_BLOCKS.len() - 1) as f32;
+    seconds
+        .iter()
+        .map(|s| SPARK_BLOCKS[(*s as f32 / max as f32 * top).round() as usize])
+        .collect()
+}
+
+/// Heart rate as % of the heart rate reserve (Karvonen), 0 at or below resting.
+/// `None` unless both resting and max heart rate are given.
+fn percent_of_reserve(heart_rate: u32, options: &ViewOptions) -> Option<u32> {
+    let (resting, max) = (options.resting_hr?, options.max_hr?);
+    let above_resting = heart_rate.saturating_sub(resting) as f64;
+    Some((above_resting / (max - resting) as f64 * 100.0).round() as u32)
+}
+
+/// Formats elapsed seconds as `H:MM:SS`, or `M:SS` under an hour.
+fn format_elapsed(seconds: u32) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Parses a pace given as `M:SS` into seconds per km.
+pub fn parse_pace(s: &str) -> Result<u32, String> {
+    let (minutes, seconds) = s
+        .split_once(':')
+        .ok_or(format!("pace must be M:SS: {}", s))?;
+    let minutes = minutes
+        .parse::<u32>()
+        .map_err(|_| format!("pace must be M:SS: {}", s))?;
+    let seconds = seconds
+        .parse::<u32>()
+        .ok()
+        .filter(|seconds| *seconds < 60)
+        .ok_or(format!("pace must be M:SS: {}", s))?;
+    Ok(minutes * 60 + seconds)
+}
+
+/// Formats seconds per km as `M:SS`.
+fn format_pace(seconds_per_km: f64, rounding: PaceRounding) -> String {
+    let seconds = match rounding {
+        PaceRounding::Floor => seconds_per_km.floor(),
+        PaceRounding::Round => seconds_per_km.round(),
+        PaceRounding::Ceil => seconds_per_km.ceil(),
+    } as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
 
 /// Handlebars helper for padding left.
 /// usage: {{pad_left value width}}
@@ -68,20 +412,365 @@ fn pad_left_helper(
     Ok(())
 }
 
-pub fn get(
-    output: fitbit::ActivityOutput,
-    template_name: &String,
-) -> Result<String, Box<dyn Error>> {
-    if output.distance.is_none() {
-        return Ok(String::new());
+/// Block helper that keeps its content monospaced in the markup.
+/// usage: {{#mono}}...{{/mono}}
+#[derive(Clone, Copy)]
+struct MonoHelper(Markup);
+
+impl handlebars::HelperDef for MonoHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &handlebars::Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc handlebars::Context,
+        rc: &mut handlebars::RenderContext<'reg, 'rc>,
+        out: &mut dyn handlebars::Output,
+    ) -> handlebars::HelperResult {
+        let (open, close) = match self.0 {
+            Markup::Plain => ("", ""),
+            Markup::Markdown => ("```\n", "```\n"),
+            Markup::Html => ("<pre>", "</pre>\n"),
+        };
+        out.write(open)?;
+        if let Some(template) = h.template() {
+            template.render(r, ctx, rc, out)?;
+        }
+        out.write(close)?;
+        Ok(())
     }
+}
+
+/// Ends every line outside `<pre>` with `<br>`.
+fn html_line_breaks(text: &str) -> String {
+    let mut html = String::new();
+    let mut in_pre = false;
+    for line in text.lines() {
+        in_pre |= line.starts_with("<pre>");
+        html.push_str(line);
+        if line.ends_with("</pre>") {
+            in_pre = false;
+        } else if !in_pre {
+            html.push_str("<br>");
+        }
+        html.push('\n');
+    }
+    html
+}
+
+fn register_helpers(handlebars: &mut Handlebars, options: &ViewOptions) {
+    handlebars.register_helper("pad_left", Box::new(pad_left_helper));
+    handlebars.register_helper("mono", Box::new(MonoHelper(options.markup)));
+}
+
+/// Returns the names of the run templates found in the template directory, sorted.
+/// The names are what `--template` accepts (file names without `.hbs`), so the summary
+/// template, which cannot render a run, is left out.
+pub fn template_names() -> io::Result<Vec<String>> {
+    let mut names = fs::read_dir(TEMPLATE_PATH)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "hbs"))
+        .filter_map(|path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(|stem| stem.to_owned())
+        })
+        .filter(|name| name != SUMMARY_TEMPLATE)
+        .collect::<Vec<String>>();
+    names.sort();
+    Ok(names)
+}
+
+/// Renders any view model, a single run or a summary, with the template.
+pub fn get<T: Serialize>(
+    model: &T,
+    template_name: &str,
+    options: &ViewOptions,
+) -> Result<String, Box<dyn Error>> {
     let mut handlebars = Handlebars::new();
     handlebars.register_template_file(
         "template",
         format!("{}/{}.hbs", TEMPLATE_PATH, template_name),
     )?;
-    handlebars.register_helper("pad_left", Box::new(pad_left_helper));
-    let view_model = ActivityViewModel::from_output(output);
-    let view = handlebars.render("template", &view_model)?;
-    Ok(view)
+    render(handlebars, model, options)
+}
+
+/// Like `get`, with the template given as a string instead of a file name.
+pub fn get_inline<T: Serialize>(
+    model: &T,
+    template: &str,
+    options: &ViewOptions,
+) -> Result<String, Box<dyn Error>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_template_string("template", template)?;
+    render(handlebars, model, options)
+}
+
+fn render<T: Serialize>(
+    mut handlebars: Handlebars,
+    model: &T,
+    options: &ViewOptions,
+) -> Result<String, Box<dyn Error>> {
+    register_helpers(&mut handlebars, options);
+    // only HTML needs `<` and `&` escaped, in other markups they would show as entities
+    if options.markup != Markup::Html {
+        handlebars.register_escape_fn(handlebars::no_escape);
+    }
+    let view = handlebars.render("template", model)?;
+    match options.markup {
+        Markup::Html => Ok(html_line_breaks(&view)),
+        _ => Ok(view),
+    }
+}
+
+/// Minimal plain text summary that does not depend on any template file.
+pub fn get_plain(view_model: &ActivityViewModel) -> String {
+    format!(
+        "🏃 {} 🏃\n{} km\n{} min.\n{} /km\navg: {} bpm\n",
+        view_model.start_time,
+        view_model.distance,
+        view_model.duration_in_min,
+        view_model.pace,
+        view_model.heart_rate_average,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_pace_rounding() {
+        assert_eq!(format_pace(329.5, PaceRounding::Floor), "5:29");
+        assert_eq!(format_pace(329.5, PaceRounding::Round), "5:30");
+        assert_eq!(format_pace(329.5, PaceRounding::Ceil), "5:30");
+        assert_eq!(format_pace(329.4, PaceRounding::Round), "5:29");
+        assert_eq!(format_pace(329.4, PaceRounding::Ceil), "5:30");
+    }
+
+    #[test]
+    fn test_mono_markup() {
+        let render = |markup| {
+            let mut handlebars = Handlebars::new();
+            let options = ViewOptions {
+                markup,
+                ..Default::default()
+            };
+            register_helpers(&mut handlebars, &options);
+            handlebars
+                .render_template("split:\n{{#mono}}\n  5:30\n{{/mono}}\n#running\n", &())
+                .unwrap()
+        };
+        assert_eq!(render(Markup::Plain), "split:\n  5:30\n#running\n");
+        assert_eq!(
+            render(Markup::Markdown),
+            "split:\n```\n  5:30\n```\n#running\n"
+        );
+        assert_eq!(
+            html_line_breaks(&render(Markup::Html)),
+            "split:<br>\n<pre>  5:30\n</pre>\n#running<br>\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_by_markup() {
+        let model = serde_json::json!({ "note": "<b>fast</b> & easy" });
+        let render = |markup| {
+            let options = ViewOptions {
+                markup,
+                ..Default::default()
+            };
+            get_inline(&model, "{{ note }}", &options).unwrap()
+        };
+        assert_eq!(render(Markup::Plain), "<b>fast</b> & easy");
+        assert_eq!(
+            render(Markup::Html),
+            "&lt;b&gt;fast&lt;/b&gt; &amp; easy<br>\n"
+        );
+    }
+
+    #[test]
+    fn test_percent_of_reserve() {
+        let options = ViewOptions {
+            resting_hr: Some(50),
+            max_hr: Some(190),
+            ..Default::default()
+        };
+        assert_eq!(percent_of_reserve(155, &options), Some(75));
+        assert_eq!(percent_of_reserve(45, &options), Some(0));
+        assert_eq!(percent_of_reserve(155, &ViewOptions::default()), None);
+    }
+
+    #[test]
+    fn test_split_label() {
+        assert_eq!(SplitLabel::Km.label(1), "km 1");
+        assert_eq!(SplitLabel::Lap.label(2), "Lap 2");
+        assert_eq!(SplitLabel::Cumulative.label(3), "3 km");
+    }
+
+    #[test]
+    fn test_format_thousands() {
+        let number = NumberFormat::default();
+        assert_eq!(number.grouped(12.4), "12");
+        assert_eq!(number.grouped(1234.0), "1,234");
+        assert_eq!(number.grouped(1234567.6), "1,234,568");
+        assert_eq!(number.number(1234.5678, 3), "1234.568");
+    }
+
+    #[test]
+    fn test_locale_number_format() {
+        let output = fitbit::ActivityOutput {
+            start_time: "2023-05-01T07:00:00.000+09:00".to_owned(),
+            distance: Some(5.25),
+            duration: 1_800_000,
+            calories: 12345,
+            week_distance: Some(32.44),
+            ..Default::default()
+        };
+        let view_model = |locale| {
+            let options = ViewOptions {
+                number_format: parse_locale(locale).unwrap(),
+                ..Default::default()
+            };
+            ActivityViewModel::from_output(&output, &options).unwrap()
+        };
+        assert_eq!(view_model("C").calories, "12345");
+        assert_eq!(view_model("en_US").calories, "12,345");
+        assert_eq!(view_model("de-DE").calories, "12.345");
+        assert_eq!(view_model("de-DE").distance, "5,250");
+        assert_eq!(view_model("de-DE").week_total.unwrap(), "32,4");
+        assert_eq!(view_model("fr").calories, "12\u{202f}345");
+        assert!(parse_locale("xx").is_err());
+    }
+
+    #[test]
+    fn test_distance_discrepancy() {
+        let output = |gps_distance| fitbit::ActivityOutput {
+            start_time: "2023-05-01T07:00:00.000+09:00".to_owned(),
+            distance: Some(5.0),
+            duration: 1_800_000,
+            gps_distance,
+            ..Default::default()
+        };
+        let view_model =
+            |gps| ActivityViewModel::from_output(&output(gps), &ViewOptions::default()).unwrap();
+        assert_eq!(view_model(Some(5.16)).gps_distance.unwrap(), "5.160");
+        assert_eq!(
+            view_model(Some(5.16)).distance_discrepancy.unwrap(),
+            "+3.2%"
+        );
+        assert_eq!(view_model(Some(4.9)).distance_discrepancy.unwrap(), "-2.0%");
+        assert_eq!(view_model(Some(5.001)).gps_distance, None);
+        assert_eq!(view_model(None).distance_discrepancy, None);
+    }
+
+    #[test]
+    fn test_duration_source() {
+        let output = |active_duration| fitbit::ActivityOutput {
+            start_time: "2023-05-01T07:00:00.000+09:00".to_owned(),
+            distance: Some(5.0),
+            duration: 1_800_000,
+            active_duration,
+            ..Default::default()
+        };
+        let view_model = |active_duration, duration_source| {
+            let options = ViewOptions {
+                duration_source,
+                ..Default::default()
+            };
+            ActivityViewModel::from_output(&output(active_duration), &options).unwrap()
+        };
+        let active = view_model(Some(1_500_000), DurationSource::Active);
+        assert_eq!(
+            (active.duration_in_min, active.pace),
+            ("25.000".into(), "5:00".into())
+        );
+        let total = view_model(Some(1_500_000), DurationSource::Total);
+        assert_eq!(
+            (total.duration_in_min, total.pace),
+            ("30.000".into(), "6:00".into())
+        );
+        assert_eq!(view_model(None, DurationSource::Active).pace, "6:00");
+    }
+
+    #[test]
+    fn test_compact_template() {
+        let output = fitbit::ActivityOutput {
+            start_time: "2023-05-01T07:00:00.000+09:00".to_owned(),
+            distance: Some(10.5),
+            duration: 3_150_000,
+            heart_rate_average: 131,
+            note: Some("a long note that is not part of the compact line".to_owned()),
+            ..Default::default()
+        };
+        let options = ViewOptions::default();
+        let view_model = ActivityViewModel::from_output(&output, &options).unwrap();
+        let text = get(&view_model, "compact", &options).unwrap();
+        assert_eq!(text, "🏃 10.5km 52:30 (5:00/km) ♥131\n");
+        assert!(text.trim_end().chars().count() <= 100);
+    }
+
+    /// Set `UPDATE_GOLDEN=1` to rewrite the golden file after an intended change.
+    #[test]
+    fn test_default_template_golden() {
+        let golden_path = "data/golden_run.default.txt";
+        let output = fitbit::ActivityOutput::from_fixture(
+            "data/golden_run.xml",
+            &fitbit::SummaryOptions::default(),
+        );
+        let options = ViewOptions::default();
+        let view_model = ActivityViewModel::from_output(&output, &options).unwrap();
+        let text = get(&view_model, "default", &options).unwrap();
+        if std::env::var("UPDATE_GOLDEN").is_ok() {
+            std::fs::write(golden_path, &text).unwrap();
+        }
+        let golden = std::fs::read_to_string(golden_path)
+            .unwrap_or_else(|_| panic!("Failed to read from file: {}", golden_path));
+        assert_eq!(text, golden);
+    }
+
+    #[test]
+    fn test_get_inline() {
+        let options = ViewOptions::default();
+        let model = serde_json::json!({ "distance": "5.000", "duration_in_min": "30.000" });
+        assert_eq!(
+            get_inline(&model, "{{distance}} km in {{duration_in_min}}", &options).unwrap(),
+            "5.000 km in 30.000"
+        );
+        assert!(get_inline(&model, "{{#if distance}} km", &options).is_err());
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(59), "0:59");
+        assert_eq!(format_elapsed(1505), "25:05");
+        assert_eq!(format_elapsed(3725), "1:02:05");
+    }
+
+    #[test]
+    fn test_zone_sparkline() {
+        let details = vec![
+            ("-150".to_owned(), 1800),
+            ("<115".to_owned(), 240),
+            (">150".to_owned(), 900),
+        ];
+        assert_eq!(zone_sparkline(&details), "▂█▅");
+        assert_eq!(zone_sparkline(&[(">150".to_owned(), 60)]), "▁▁█");
+        assert_eq!(zone_sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_template_names() {
+        let names = template_names().unwrap();
+        assert!(names.contains(&"default".to_owned()));
+        assert!(!names.contains(&SUMMARY_TEMPLATE.to_owned()));
+    }
+
+    #[test]
+    fn test_parse_pace() {
+        assert_eq!(parse_pace("5:30"), Ok(330));
+        assert_eq!(parse_pace("12:05"), Ok(725));
+        assert!(parse_pace("5:60").is_err());
+        assert!(parse_pace("530").is_err());
+    }
 }
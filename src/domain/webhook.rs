@@ -0,0 +1,64 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{Client, Result};
+
+#[derive(Debug)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Sent with every request, after and so over the default `Content-Type`.
+    pub headers: HeaderMap,
+    pub client: Client,
+}
+
+/// Parses `Key:Value`, e.g. `Authorization: Bearer xxx`.
+pub fn parse_header(header: &str) -> std::result::Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("expected Key:Value, got {}", header))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name: {}", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("invalid value for header {}", name))?;
+    Ok((name, value))
+}
+
+/// Parses `Key:Value;Key2:Value2`.
+pub fn parse_headers(headers: &str) -> std::result::Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for header in headers.split(';').filter(|h| !h.trim().is_empty()) {
+        let (name, value) = parse_header(header)?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+/// Posts the text as `{"text": ...}`.
+pub async fn post(config: &WebhookConfig, text: &String) -> Result<()> {
+    let body = serde_json::json!({ "text": text }).to_string();
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.extend(config.headers.clone());
+    config
+        .client
+        .post(&config.url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = parse_headers("Authorization: Bearer abc;X-Route:runs").unwrap();
+        assert_eq!(headers["authorization"], "Bearer abc");
+        assert_eq!(headers["x-route"], "runs");
+        assert!(parse_headers("Authorization Bearer abc").is_err());
+        assert!(parse_headers("Bad Name: x").is_err());
+        assert!(parse_headers("X-Ok: line\nbreak").is_err());
+    }
+}
@@ -1,71 +1,632 @@
-use chrono::NaiveDate;
-use clap::{Parser, ValueEnum};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use cron::Schedule;
 use dotenvy::{dotenv, var};
-use reqwest::Result;
+use futures_util::future;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Serialize;
+use unicode_segmentation::UnicodeSegmentation;
 
 mod domain;
-use domain::{fitbit, mastodon, misskey, view};
+mod serve;
+use domain::{
+    condition, fitbit, http, mastodon, media, misskey, outbox, state, summary, totals, view,
+    webhook,
+};
+
+const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_FITBIT_TOKEN_SKEW_SECS: i64 = 60;
+const DEFAULT_FITBIT_API_VERSION: &str = "1";
+const DEFAULT_MIN_TRACKPOINTS: usize = 10;
+const DEFAULT_POST_MAX_RETRIES: u32 = 2;
+const COMPACT_TEMPLATE: &str = "compact";
 
 struct AppConfig {
+    http: http::HttpConfig,
     fitbit_api_url: String,
+    fitbit_api_version: String,
     fitbit_client_id: String,
     fitbit_client_secret: String,
-    mastodon_api_url: String,
-    mastodon_access_token: String,
-    misskey_api_url: String,
-    misskey_access_token: String,
+    fitbit_token_skew_secs: i64,
+    min_trackpoints: usize,
+    /// Retries of a post that failed transiently.
+    post_max_retries: u32,
+    // Platform credentials are only required when posting, see `AppConfig::require`.
+    mastodon_api_url: Option<String>,
+    mastodon_access_token: Option<String>,
+    misskey_api_url: Option<String>,
+    misskey_access_token: Option<String>,
+    /// Named accounts from `MASTODON_ACCOUNTS`/`MISSKEY_ACCOUNTS`, besides the ones above.
+    mastodon_accounts: Vec<Account>,
+    misskey_accounts: Vec<Account>,
+    webhook_url: Option<String>,
+    /// From `WEBHOOK_HEADERS`; `--webhook-header` adds to these.
+    webhook_headers: HeaderMap,
+}
+
+/// An account named in `<PLATFORM>_ACCOUNTS`, configured by `<PLATFORM>_<NAME>_API_URL`
+/// and `<PLATFORM>_<NAME>_ACCESS_TOKEN`, which like the unprefixed ones are only required
+/// when posting, see `Account::credentials`.
+#[derive(Debug)]
+struct Account {
+    name: String,
+    /// `<PLATFORM>_<NAME>`, the prefix of the account's variables.
+    var_prefix: String,
+    api_url: Option<String>,
+    access_token: Option<String>,
+}
+
+impl Account {
+    /// The API URL and access token, an error naming the first of them that is unset.
+    fn credentials(&self) -> Result<(String, String)> {
+        let require =
+            |value, key| AppConfig::require(value, &format!("{}_{}", self.var_prefix, key));
+        Ok((
+            require(&self.api_url, "API_URL")?,
+            require(&self.access_token, "ACCESS_TOKEN")?,
+        ))
+    }
+}
+
+/// `--account` name of the account configured by the unprefixed variables.
+const DEFAULT_ACCOUNT: &str = "default";
+
+fn load_accounts(platform: &str) -> Result<Vec<Account>> {
+    let names = optional_var(&format!("{}_ACCOUNTS", platform)).unwrap_or_default();
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if name == DEFAULT_ACCOUNT {
+                return Err(anyhow!(
+                    "{}_ACCOUNTS: {} is reserved.",
+                    platform,
+                    DEFAULT_ACCOUNT
+                ));
+            }
+            let var_prefix = format!("{}_{}", platform, name.to_uppercase());
+            Ok(Account {
+                name: name.to_owned(),
+                api_url: optional_var(&format!("{}_API_URL", var_prefix)),
+                access_token: optional_var(&format!("{}_ACCESS_TOKEN", var_prefix)),
+                var_prefix,
+            })
+        })
+        .collect()
 }
 
-#[derive(Clone, ValueEnum, Debug)]
+#[derive(Clone, ValueEnum, Debug, PartialEq, Eq)]
 enum Platform {
     Mastodon,
     Misskey,
+    /// POST `{"text": ...}` as JSON to `WEBHOOK_URL`
+    Webhook,
+}
+
+impl Platform {
+    /// Maximum number of photos a post can have, `None` if the platform takes no media.
+    fn media_limit(&self) -> Option<usize> {
+        match self {
+            Platform::Mastodon => Some(mastodon::MEDIA_LIMIT),
+            Platform::Misskey => Some(misskey::MEDIA_LIMIT),
+            Platform::Webhook => None,
+        }
+    }
+
+    /// Default maximum post length of the platform.
+    fn char_limit(&self) -> usize {
+        match self {
+            Platform::Mastodon => 500,
+            Platform::Misskey => 3000,
+            // no limit of its own, just keep it to a size any receiver takes
+            Platform::Webhook => 10000,
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+#[derive(Clone, ValueEnum, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// render the templates and post
+    Text,
+    /// print the activity as JSON and skip posting
+    Json,
+}
+
+/// `--template` value: either `<name>` or `<platform>=<name>`.
+#[derive(Clone, Debug)]
+struct TemplateArg {
+    platform: Option<Platform>,
+    name: String,
+}
+
+fn parse_template_arg(s: &str) -> std::result::Result<TemplateArg, String> {
+    match s.split_once('=') {
+        Some((platform, name)) => Ok(TemplateArg {
+            platform: Some(Platform::from_str(platform, true)?),
+            name: name.to_owned(),
+        }),
+        None => Ok(TemplateArg {
+            platform: None,
+            name: s.to_owned(),
+        }),
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List available template names
+    Templates,
+    /// List the activities since --since, optionally picking one to report
+    List {
+        /// prompt for the number of the activity to preview/post
+        #[arg(long, default_value_t = false)]
+        interactive: bool,
+    },
+    /// Revoke the stored Fitbit token and delete credentials.json
+    Logout,
+    /// Post what --outbox queued in outbox.jsonl, keeping the posts that fail again
+    FlushOutbox,
+    /// Inspect the loaded configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the resolved configuration with secrets masked
+    Show,
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true, subcommand_precedence_over_arg = true)]
 struct CliArgs {
-    /// Date to fetch (from)
-    #[arg(short, long)]
-    since: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Platform name to post the report
-    #[arg(value_enum, default_value_t = crate::Platform::Misskey)]
-    platform: Platform,
+    /// Date to fetch (from) as YYYY-MM-DD, or `week`/`month` for the start of the current
+    /// week (see --week-start) or month
+    // global so that `list --since` works, which rules out `required_unless_present_any`
+    // on arguments of the top level only; checked in `main` instead
+    #[arg(short, long, global = true)]
+    since: Option<String>,
 
-    /// template name (default: default)
-    #[arg(short, long, default_value = "default")]
-    template: String,
+    /// Platform names to post the report
+    #[arg(value_enum, default_values_t = [crate::Platform::Misskey])]
+    platforms: Vec<Platform>,
 
-    /// is preview mode ON
+    /// queue a post that fails transiently in outbox.jsonl for flush-outbox instead of failing
     #[arg(long, default_value_t = false)]
+    outbox: bool,
+
+    /// Mastodon/Misskey account to post with, can be repeated (default: every configured one)
+    #[arg(long = "account", value_name = "NAME")]
+    accounts: Vec<String>,
+
+    /// template name, or `<platform>=<name>` to use it for that platform only (default: default)
+    #[arg(short, long, value_parser = parse_template_arg, default_value = "default")]
+    template: Vec<TemplateArg>,
+
+    /// template string to render instead of a template file, e.g. "{{distance}} km"
+    #[arg(long, conflicts_with = "template")]
+    template_inline: Option<String>,
+
+    /// render a single line with the compact template, e.g. for a status bar
+    #[arg(long, default_value_t = false, conflicts_with_all = ["template", "template_inline"])]
+    compact: bool,
+
+    /// with --preview, render every template but the summary one, each under its name
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "preview",
+        conflicts_with_all = ["template", "template_inline", "compact"]
+    )]
+    template_all: bool,
+
+    /// is preview mode ON (render and print without posting)
+    #[arg(long, visible_alias = "no-post", default_value_t = false)]
     preview: bool,
+
+    /// output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// omit the preview mode banner
+    #[arg(long, default_value_t = false, requires = "preview")]
+    no_banner: bool,
+
+    /// box each preview with its length checked against the platform limit
+    #[arg(long, default_value_t = false, requires = "preview")]
+    preview_box: bool,
+
+    /// schedule the post at the given time (RFC 3339), Mastodon only
+    #[arg(long, value_parser = DateTime::parse_from_rfc3339)]
+    schedule_at: Option<DateTime<FixedOffset>>,
+
+    /// fetch from the day after the last posted run (from --since or today on the first run)
+    #[arg(long, default_value_t = false)]
+    since_last_post: bool,
+
+    /// render from a local TCX file instead of fetching from Fitbit
+    #[arg(long)]
+    tcx_file: Option<PathBuf>,
+
+    /// post the runs of the week or month from --since instead of a single run,
+    /// rendered with the summary template
+    #[arg(long, value_enum, conflicts_with_all = ["tcx_file", "from_json"])]
+    summary: Option<summary::Period>,
+
+    /// first day of the week for `--since week`, e.g. sun
+    #[arg(long, value_parser = summary::parse_weekday, default_value = "mon")]
+    week_start: chrono::Weekday,
+
+    /// print the raw TCX of the latest run since --since, without summarizing or posting
+    #[arg(long, default_value_t = false, conflicts_with_all = ["tcx_file", "from_json"])]
+    dump_tcx: bool,
+
+    /// with --dump-tcx, the Fitbit log id of the activity to dump instead
+    #[arg(long, requires = "dump_tcx")]
+    log_id: Option<u64>,
+
+    /// with --dump-tcx, write the TCX to this file instead of stdout
+    #[arg(long, value_name = "PATH", requires = "dump_tcx")]
+    output_file: Option<PathBuf>,
+
+    /// render a run saved earlier with --format json instead of fetching from Fitbit
+    #[arg(long, value_name = "PATH", conflicts_with = "tcx_file")]
+    from_json: Option<PathBuf>,
+
+    /// compute heart rate stats only while moving
+    #[arg(long, default_value_t = false)]
+    moving_only: bool,
+
+    /// keep running and post on the --cron schedule, serving /healthz and /metrics
+    #[arg(long, default_value_t = false)]
+    serve: bool,
+
+    /// port for /healthz and /metrics in serve mode
+    #[arg(long, default_value_t = 8080, requires = "serve")]
+    port: u16,
+
+    /// schedule in serve mode (sec min hour day month weekday)
+    #[arg(long, value_parser = Schedule::from_str, default_value = "0 0 8 * * *", requires = "serve")]
+    cron: Schedule,
+
+    /// target pace as M:SS to annotate each split as ahead/behind
+    #[arg(long, value_parser = view::parse_pace)]
+    target_pace: Option<u32>,
+
+    /// distances in km to report the cumulative time at, e.g. 5,10,21.1
+    #[arg(long, value_delimiter = ',')]
+    split_markers: Vec<f64>,
+
+    /// smooth heart rates with a moving average over this many samples before zoning
+    #[arg(long)]
+    smooth_hr: Option<usize>,
+
+    /// append the distances of this many previous runs
+    #[arg(long, visible_alias = "append-previous")]
+    history: Option<usize>,
+
+    /// post a built-in plain text summary when the template fails to render
+    #[arg(long, default_value_t = false)]
+    fallback_plain: bool,
+
+    /// post only if the run meets the condition: `distance>=10` (km), `duration>30` (min.) or `pr`
+    #[arg(long, value_parser = condition::parse)]
+    post_if: Option<condition::PostCondition>,
+
+    /// activity name to look for
+    #[arg(long, default_value = "Run")]
+    activity: String,
+
+    /// regex to match the activity name with, takes precedence over --activity
+    #[arg(long, value_parser = Regex::new)]
+    activity_pattern: Option<Regex>,
+
+    /// edit the post made by the last --edit-latest run instead of posting a new one
+    #[arg(long, default_value_t = false, conflicts_with = "schedule_at")]
+    edit_latest: bool,
+
+    /// take splits at every full km (distance) or every N seconds (time:N)
+    #[arg(long, value_parser = fitbit::parse_split_by, default_value = "distance")]
+    split_by: fitbit::SplitBy,
+
+    /// image to attach to the post, can be repeated
+    #[arg(long = "photo", value_name = "PATH", conflicts_with = "edit_latest")]
+    photos: Vec<PathBuf>,
+
+    /// resting heart rate, with --max-hr shows heart rates as % of the reserve and the TRIMP
+    #[arg(long)]
+    resting_hr: Option<u32>,
+
+    /// max heart rate, with --resting-hr shows heart rates as % of the reserve and the TRIMP
+    #[arg(long)]
+    max_hr: Option<u32>,
+
+    /// warn when the GPS distance differs from the Fitbit-reported one by more than this many %
+    #[arg(long, value_name = "PERCENT")]
+    distance_tolerance: Option<f64>,
+
+    /// proxy URL for every request (http, https, socks5), overrides ALL_PROXY
+    #[arg(long, value_parser = http::parse_proxy)]
+    proxy: Option<String>,
+
+    /// extra header `Key:Value` for the webhook platform, can be repeated
+    #[arg(long, value_parser = webhook::parse_header)]
+    webhook_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// markup flavor of the text, e.g. markdown for Discord or Slack
+    #[arg(long, value_enum, default_value_t = view::Markup::Plain)]
+    markup: view::Markup,
+
+    /// decimal separator and thousands grouping of numbers, e.g. de or fr_FR; default `.` without grouping
+    #[arg(long, value_parser = view::parse_locale)]
+    locale: Option<view::NumberFormat>,
+
+    /// label distance splits as km N, Lap N or the cumulative distance
+    #[arg(long, value_enum, default_value_t = view::SplitLabel::Km)]
+    split_label: view::SplitLabel,
+
+    /// also show the steps and distance of the whole day of the run
+    #[arg(long, default_value_t = false)]
+    daily: bool,
+
+    /// also show the distance of the runs of the week of the run up to its day (see --week-start)
+    #[arg(long, default_value_t = false)]
+    week_total: bool,
+
+    /// add the run to the lifetime total in totals.json and show the total
+    #[arg(long, default_value_t = false)]
+    lifetime: bool,
+
+    /// rebuild totals.json from every run since --since, then exit
+    #[arg(long, default_value_t = false, conflicts_with_all = ["tcx_file", "from_json"])]
+    recompute_lifetime: bool,
+
+    /// how to round pace shown as M:SS
+    #[arg(long, value_enum, default_value_t = view::PaceRounding::Round)]
+    pace_rounding: view::PaceRounding,
+
+    /// duration the shown duration and pace are based on
+    #[arg(long, value_enum, default_value_t = view::DurationSource::Active)]
+    duration_source: view::DurationSource,
+}
+
+impl CliArgs {
+    fn activity_matcher(&self) -> fitbit::ActivityMatcher {
+        match &self.activity_pattern {
+            Some(pattern) => fitbit::ActivityMatcher::Pattern(pattern.clone()),
+            None => fitbit::ActivityMatcher::Name(self.activity.to_owned()),
+        }
+    }
+
+    fn view_options(&self) -> view::ViewOptions {
+        view::ViewOptions {
+            pace_rounding: self.pace_rounding,
+            duration_source: self.duration_source,
+            number_format: self.locale.unwrap_or_default(),
+            markup: self.markup,
+            split_label: self.split_label,
+            target_pace: self.target_pace,
+            resting_hr: self.resting_hr,
+            max_hr: self.max_hr,
+        }
+    }
+
+    /// Renders with `--template-inline` if given, otherwise with the named template file.
+    fn render<T: Serialize>(
+        &self,
+        model: &T,
+        template_name: &str,
+        options: &view::ViewOptions,
+    ) -> std::result::Result<String, Box<dyn Error>> {
+        match &self.template_inline {
+            Some(template) => view::get_inline(model, template, options),
+            None => view::get(model, template_name, options),
+        }
+    }
+
+    /// Template for the platform: a platform-specific `--template` wins over a plain one.
+    fn template_for(&self, platform: &Platform) -> &str {
+        if self.compact {
+            return COMPACT_TEMPLATE;
+        }
+        self.template
+            .iter()
+            .rev()
+            .find(|t| t.platform.as_ref() == Some(platform))
+            .or_else(|| self.template.iter().rev().find(|t| t.platform.is_none()))
+            .map(|t| t.name.as_str())
+            .unwrap_or("default")
+    }
+}
+
+/// An unset or empty variable is treated the same.
+fn optional_var(name: &str) -> Option<String> {
+    var(name).ok().filter(|v| !v.is_empty())
 }
 
 impl AppConfig {
-    fn load() -> Self {
+    /// `proxy` is `--proxy`, which takes the place of `ALL_PROXY`.
+    fn load(proxy: Option<String>) -> Result<Self> {
         dotenv().expect("Failed to load .env.");
 
+        let http_timeout_secs = var("HTTP_TIMEOUT_SECS").map_or(DEFAULT_HTTP_TIMEOUT_SECS, |v| {
+            v.parse()
+                .expect("HTTP_TIMEOUT_SECS must be a number of seconds.")
+        });
         let fitbit_api_url = var("FITBIT_API_URL").expect("Failed to get FITBIT_API_URL.");
+        let fitbit_api_version = match optional_var("FITBIT_API_VERSION") {
+            Some(v) => {
+                fitbit::parse_api_version(&v).map_err(|e| anyhow!("FITBIT_API_VERSION: {}", e))?
+            }
+            None => DEFAULT_FITBIT_API_VERSION.to_owned(),
+        };
         let fitbit_client_id = var("FITBIT_CLIENT_ID").expect("Failed to get FITBIT_CLIENT_ID.");
         let fitbit_client_secret =
             var("FITBIT_CLIENT_SECRET").expect("Failed to get FITBIT_CLIENT_SECRET.");
-        let mastodon_api_url = var("MASTODON_API_URL").expect("Failed to get MASTODON_API_URL.");
-        let mastodon_access_token =
-            var("MASTODON_ACCESS_TOKEN").expect("Failed to get MASTODON_ACCESS_TOKEN.");
-        let misskey_api_url = var("MISSKEY_API_URL").expect("Failed to get MISSKEY_API_URL.");
-        let misskey_access_token =
-            var("MISSKEY_ACCESS_TOKEN").expect("Failed to get MISSKEY_ACCESS_TOKEN.");
+        let fitbit_token_skew_secs =
+            var("FITBIT_TOKEN_SKEW_SECS").map_or(DEFAULT_FITBIT_TOKEN_SKEW_SECS, |v| {
+                v.parse()
+                    .expect("FITBIT_TOKEN_SKEW_SECS must be a number of seconds.")
+            });
+        let min_trackpoints = var("MIN_TRACKPOINTS").map_or(DEFAULT_MIN_TRACKPOINTS, |v| {
+            v.parse().expect("MIN_TRACKPOINTS must be a number.")
+        });
+        // reqwest reads HTTP_PROXY and HTTPS_PROXY by itself, but not ALL_PROXY
+        let proxy = match proxy {
+            Some(proxy) => Some(proxy),
+            None => optional_var("ALL_PROXY")
+                .or_else(|| optional_var("all_proxy"))
+                .map(|url| http::parse_proxy(&url).map_err(|e| anyhow!("ALL_PROXY: {}", e)))
+                .transpose()?,
+        };
+        let post_max_retries = var("POST_MAX_RETRIES").map_or(DEFAULT_POST_MAX_RETRIES, |v| {
+            v.parse().expect("POST_MAX_RETRIES must be a number.")
+        });
+        let mastodon_api_url = optional_var("MASTODON_API_URL");
+        let mastodon_access_token = optional_var("MASTODON_ACCESS_TOKEN");
+        let misskey_api_url = optional_var("MISSKEY_API_URL");
+        let misskey_access_token = optional_var("MISSKEY_ACCESS_TOKEN");
+        let mastodon_accounts = load_accounts("MASTODON")?;
+        let misskey_accounts = load_accounts("MISSKEY")?;
+        let webhook_url = optional_var("WEBHOOK_URL");
+        let webhook_headers = match optional_var("WEBHOOK_HEADERS") {
+            Some(v) => webhook::parse_headers(&v).map_err(|e| anyhow!("WEBHOOK_HEADERS: {}", e))?,
+            None => HeaderMap::new(),
+        };
 
-        Self {
+        Ok(Self {
+            http: http::HttpConfig {
+                timeout: Duration::from_secs(http_timeout_secs),
+                proxy,
+                user_agent: optional_var("HTTP_USER_AGENT")
+                    .unwrap_or_else(http::default_user_agent),
+            },
             fitbit_api_url,
+            fitbit_api_version,
             fitbit_client_id,
             fitbit_client_secret,
+            fitbit_token_skew_secs,
+            min_trackpoints,
+            post_max_retries,
             mastodon_api_url,
             mastodon_access_token,
             misskey_api_url,
             misskey_access_token,
-        }
+            mastodon_accounts,
+            misskey_accounts,
+            webhook_url,
+            webhook_headers,
+        })
+    }
+
+    /// The value of a variable needed only to post, an error naming it when it is unset.
+    fn require(value: &Option<String>, name: &str) -> Result<String> {
+        value
+            .to_owned()
+            .ok_or_else(|| anyhow!("Failed to get {}.", name))
+    }
+}
+
+/// Keeps the last 4 characters of a secret so it can be told apart without leaking it.
+fn mask_secret(secret: &str) -> String {
+    let chars = secret.chars().collect::<Vec<char>>();
+    if chars.len() <= 4 {
+        return "****".to_owned();
+    }
+    let tail = chars[chars.len() - 4..].iter().collect::<String>();
+    format!("****{}", tail)
+}
+
+/// Lists every setting, one per line, with tokens and secrets masked.
+impl fmt::Display for AppConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unset = || "(unset)".to_owned();
+        let plain = |v: &Option<String>| v.to_owned().unwrap_or_else(unset);
+        let masked = |v: &Option<String>| v.as_deref().map(mask_secret).unwrap_or_else(unset);
+
+        writeln!(f, "http_timeout_secs: {}", self.http.timeout.as_secs())?;
+        writeln!(f, "proxy: {}", masked(&self.http.proxy))?;
+        writeln!(f, "http_user_agent: {}", self.http.user_agent)?;
+        writeln!(f, "fitbit_api_url: {}", self.fitbit_api_url)?;
+        writeln!(f, "fitbit_api_version: {}", self.fitbit_api_version)?;
+        writeln!(f, "fitbit_client_id: {}", self.fitbit_client_id)?;
+        writeln!(
+            f,
+            "fitbit_client_secret: {}",
+            mask_secret(&self.fitbit_client_secret)
+        )?;
+        writeln!(f, "fitbit_token_skew_secs: {}", self.fitbit_token_skew_secs)?;
+        writeln!(f, "min_trackpoints: {}", self.min_trackpoints)?;
+        writeln!(f, "post_max_retries: {}", self.post_max_retries)?;
+        writeln!(f, "mastodon_api_url: {}", plain(&self.mastodon_api_url))?;
+        writeln!(
+            f,
+            "mastodon_access_token: {}",
+            masked(&self.mastodon_access_token)
+        )?;
+        writeln!(f, "misskey_api_url: {}", plain(&self.misskey_api_url))?;
+        writeln!(
+            f,
+            "misskey_access_token: {}",
+            masked(&self.misskey_access_token)
+        )?;
+        let accounts = |accounts: &[Account]| {
+            accounts
+                .iter()
+                .map(|a| {
+                    format!(
+                        "{}={} ({})",
+                        a.name,
+                        plain(&a.api_url),
+                        masked(&a.access_token)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        writeln!(
+            f,
+            "mastodon_accounts: {}",
+            accounts(&self.mastodon_accounts)
+        )?;
+        writeln!(f, "misskey_accounts: {}", accounts(&self.misskey_accounts))?;
+        // webhook URLs often carry their token, and so do the headers' values
+        writeln!(f, "webhook_url: {}", masked(&self.webhook_url))?;
+        let header_names = self
+            .webhook_headers
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<&str>>();
+        writeln!(f, "webhook_headers: {}", header_names.join(", "))
     }
 }
 
@@ -74,76 +635,1263 @@ struct AppContext<'a> {
     arguments: &'a CliArgs,
 }
 
-async fn run<'a>(ctx: &'a AppContext<'a>) -> Result<()> {
-    let fitbit_api = fitbit::FitbitApi::new(fitbit::FitbitApiConfig {
+impl AppContext<'_> {
+    fn summary_options(&self) -> fitbit::SummaryOptions {
+        fitbit::SummaryOptions {
+            moving_only: self.arguments.moving_only,
+            split_markers: self.arguments.split_markers.clone(),
+            smooth_hr: self.arguments.smooth_hr,
+            min_trackpoints: self.config.min_trackpoints,
+            split_by: self.arguments.split_by,
+            resting_hr: self.arguments.resting_hr,
+            max_hr: self.arguments.max_hr,
+        }
+    }
+}
+
+fn fitbit_api(ctx: &AppContext) -> reqwest::Result<fitbit::FitbitApi> {
+    fitbit::FitbitApi::new(fitbit::FitbitApiConfig {
         base_url: ctx.config.fitbit_api_url.to_owned(),
+        api_version: ctx.config.fitbit_api_version.to_owned(),
         client_id: ctx.config.fitbit_client_id.to_owned(),
         client_secret: ctx.config.fitbit_client_secret.to_owned(),
-    });
+        http: ctx.config.http.clone(),
+        token_skew: chrono::Duration::seconds(ctx.config.fitbit_token_skew_secs),
+        activity: ctx.arguments.activity_matcher(),
+    })
+}
+
+async fn connect_fitbit<'a>(
+    ctx: &'a AppContext<'a>,
+) -> Result<Option<(fitbit::FitbitApi, String)>> {
+    let fitbit_api = fitbit_api(ctx)?;
 
     let access_token = fitbit_api.access_token().await?;
-    if access_token.is_none() || access_token.as_ref().unwrap().is_empty() {
-        println!("Failed to get access token.");
-        return Ok(());
+    match access_token {
+        Some(access_token) if !access_token.is_empty() => Ok(Some((fitbit_api, access_token))),
+        _ => {
+            println!("Failed to get access token.");
+            Ok(None)
+        }
+    }
+}
+
+/// `--since`, or with `--since-last-post` the day after the last posted run, falling back
+/// to `--since` or today when nothing has been posted yet.
+fn since_date(ctx: &AppContext) -> Result<NaiveDate> {
+    if ctx.arguments.since_last_post {
+        if let Some(last_post) = load_state()?.last_post {
+            let start_time =
+                DateTime::parse_from_rfc3339(&last_post.start_time).with_context(|| {
+                    format!(
+                        "Failed to read state.json, last_post.start_time {} is not RFC 3339",
+                        last_post.start_time
+                    )
+                })?;
+            return Ok(start_time.date_naive().succ_opt().unwrap());
+        }
+    }
+    match &ctx.arguments.since {
+        Some(arg_since) => summary::parse_since(
+            arg_since,
+            Local::now().date_naive(),
+            ctx.arguments.week_start,
+        )
+        .map_err(|e| anyhow!("{}", e)),
+        None => Ok(Local::now().date_naive()),
+    }
+}
+
+fn load_state() -> Result<state::State> {
+    state::load().context("Failed to read state.json, fix or remove it")
+}
+
+/// Adds what the options ask for beyond the run itself, e.g. the previous runs.
+async fn add_run_context<'a>(
+    ctx: &'a AppContext<'a>,
+    fitbit_api: &fitbit::FitbitApi,
+    access_token: &String,
+    run: &mut fitbit::ActivityOutput,
+) -> Result<()> {
+    if let Some(count) = ctx.arguments.history {
+        run.history = fitbit_api
+            .fetch_previous_run_distances(&run.start_time, Some(count), access_token)
+            .await?;
     }
 
-    let arg_since = &ctx.arguments.since;
-    let since_date =
-        NaiveDate::parse_from_str(&arg_since, "%Y-%m-%d").expect("since must be YYYY-MM-DD.");
+    if ctx.arguments.daily {
+        let date = DateTime::parse_from_rfc3339(&run.start_time)
+            .expect("start time must be RFC 3339.")
+            .date_naive();
+        match fitbit_api.fetch_daily_summary(&date, access_token).await {
+            Ok(daily) => {
+                run.daily_steps = Some(daily.steps);
+                run.daily_distance = daily.distance;
+            }
+            Err(e) => eprintln!(
+                "Failed to get the daily summary. {}",
+                http::describe_error(&e)
+            ),
+        }
+    }
+
+    if ctx.arguments.week_total {
+        let date = DateTime::parse_from_rfc3339(&run.start_time)
+            .expect("start time must be RFC 3339.")
+            .date_naive();
+        let start = summary::parse_since("week", date, ctx.arguments.week_start).unwrap();
+        let activities = fitbit_api
+            .fetch_all_run_activities(&start, access_token)
+            .await?;
+        let days = summary::daily_distances(&activities);
+        run.week_distance = Some(days.range(start..=date).map(|(_, d)| *d as f64).sum());
+    }
+
+    if ctx.arguments.post_if == Some(condition::PostCondition::PersonalRecord) {
+        let previous = fitbit_api
+            .fetch_previous_run_distances(&run.start_time, None, access_token)
+            .await?;
+        let distance = run.distance.unwrap_or(0.0);
+        run.personal_record = Some(previous.iter().all(|d| *d < distance));
+    }
+
+    Ok(())
+}
+
+async fn fetch_latest_run<'a>(ctx: &'a AppContext<'a>) -> Result<Option<fitbit::ActivityOutput>> {
+    let Some((fitbit_api, access_token)) = connect_fitbit(ctx).await? else {
+        return Ok(None);
+    };
 
     let run = fitbit_api
-        .fetch_latest_run_activity(&since_date, &access_token.unwrap())
+        .fetch_latest_run_activity(&since_date(ctx)?, &access_token, &ctx.summary_options())
         .await?;
-    if run.is_none() {
+    let Some(mut run) = run else {
         println!("No run activity found.");
+        return Ok(None);
+    };
+    add_run_context(ctx, &fitbit_api, &access_token, &mut run).await?;
+
+    Ok(Some(run))
+}
+
+/// Prints the matching activities, numbered, and with `interactive` reports the chosen one.
+async fn list_activities<'a>(ctx: &'a AppContext<'a>, interactive: bool) -> Result<()> {
+    let Some((fitbit_api, access_token)) = connect_fitbit(ctx).await? else {
+        return Ok(());
+    };
+
+    let activities = fitbit_api
+        .fetch_run_activities(&since_date(ctx)?, &access_token)
+        .await?;
+    if activities.is_empty() {
+        println!("No run activity found.");
+        return Ok(());
+    }
+    for (n, activity) in activities.iter().enumerate() {
+        let date = DateTime::parse_from_rfc3339(&activity.startTime)
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| activity.startTime.clone());
+        let distance = activity
+            .distance
+            .map_or("-".to_owned(), |d| format!("{:.2} km", d));
+        println!(
+            "{:>3}. {}  {}  {}",
+            n + 1,
+            date,
+            activity.activityName,
+            distance
+        );
+    }
+    if !interactive {
         return Ok(());
     }
 
-    let text = view::get(run.unwrap(), &ctx.arguments.template);
+    print!("Select number > ");
+    let _ = io::stdout().flush();
+    let mut number = String::new();
+    io::stdin()
+        .read_line(&mut number)
+        .expect("Failed to read line.");
+    let activity = number
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| activities.get(i));
+    let Some(activity) = activity else {
+        println!("No such activity: {}", number.trim());
+        return Ok(());
+    };
+
+    let mut run = fitbit_api
+        .fetch_run_output(activity, &access_token, &ctx.summary_options())
+        .await?;
+    add_run_context(ctx, &fitbit_api, &access_token, &mut run).await?;
+    report(ctx, run).await
+}
+
+/// Revokes the stored token at Fitbit, then deletes it. When the token cannot be revoked,
+/// asks whether to delete the local file anyway.
+async fn logout(ctx: &AppContext<'_>) -> Result<()> {
+    let revoked = match fitbit::stored_access_token() {
+        Err(fitbit::TokensError::Missing) => {
+            println!("Already logged out.");
+            return Ok(());
+        }
+        Err(fitbit::TokensError::Invalid(e)) => {
+            println!(
+                "Failed to read credentials.json, cannot revoke the token. {}",
+                e
+            );
+            false
+        }
+        Ok(token) => match fitbit_api(ctx)?.revoke_token(&token).await {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Failed to revoke the token. {}", http::describe_error(&e));
+                false
+            }
+        },
+    };
 
-    if text.is_err() {
-        println!("Failed to create text. {}", text.err().unwrap());
+    if !revoked {
+        print!("Delete credentials.json anyway? [y/N] > ");
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .expect("Failed to read line.");
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
+        }
+    }
+    match fitbit::delete_tokens() {
+        Ok(()) if revoked => println!("Revoked the token and deleted credentials.json."),
+        Ok(()) => println!("Deleted credentials.json."),
+        Err(e) => println!("Failed to delete credentials.json. {}", e),
+    }
+    Ok(())
+}
+
+fn load_tcx_file(ctx: &AppContext, path: &Path) -> Option<fitbit::ActivityOutput> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("Failed to read {}. {}", path.display(), e);
+            return None;
+        }
+    };
+    match fitbit::summarize_tcx(&content, &ctx.summary_options()) {
+        Ok(Some(run)) => Some(run),
+        Ok(None) => {
+            println!("Not enough trackpoints in {}.", path.display());
+            None
+        }
+        Err(e) => {
+            println!("{} is not a TCX activity log. {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn load_json_file(path: &Path) -> Option<fitbit::ActivityOutput> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("Failed to read {}. {}", path.display(), e);
+            return None;
+        }
+    };
+    let run: fitbit::ActivityOutput = match serde_json::from_str(&content) {
+        Ok(run) => run,
+        Err(e) => {
+            println!(
+                "{} is not a run exported with --format json. {}",
+                path.display(),
+                e
+            );
+            return None;
+        }
+    };
+    // everything after takes start_time to be RFC 3339
+    if let Err(e) = DateTime::parse_from_rfc3339(&run.start_time) {
+        println!(
+            "{}: start_time {} is not an RFC 3339 time. {}",
+            path.display(),
+            run.start_time,
+            e
+        );
+        return None;
+    }
+    Some(run)
+}
+
+async fn run<'a>(ctx: &'a AppContext<'a>) -> Result<()> {
+    let run = match (&ctx.arguments.tcx_file, &ctx.arguments.from_json) {
+        (Some(path), _) => load_tcx_file(ctx, path),
+        (_, Some(path)) => load_json_file(path),
+        _ => fetch_latest_run(ctx).await?,
+    };
+    let Some(run) = run else {
         return Ok(());
+    };
+
+    report(ctx, run).await
+}
+
+/// Renders the run and previews, exports or posts it.
+async fn report<'a>(ctx: &'a AppContext<'a>, mut run: fitbit::ActivityOutput) -> Result<()> {
+    if let (Some(tolerance), Some(discrepancy)) =
+        (ctx.arguments.distance_tolerance, run.distance_discrepancy())
+    {
+        if discrepancy.abs() > tolerance {
+            run.warnings.push(fitbit::Warning::DistanceDiscrepancy {
+                reported: run.distance.unwrap_or(0.0),
+                gps: run.gps_distance.unwrap_or(0.0),
+            });
+        }
+    }
+    // JSON carries the warnings along, other formats have no place for them
+    if ctx.arguments.format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&run).unwrap());
+        return Ok(());
+    }
+    for warning in &run.warnings {
+        eprintln!("Warning: {}.", warning);
+    }
+
+    if !check_schedule(ctx) || !check_accounts(ctx) {
+        return Ok(());
+    }
+    check_credentials(ctx)?;
+
+    let mut lifetime = None;
+    if ctx.arguments.lifetime {
+        let totals = totals::load()?;
+        let distance = run.distance.unwrap_or(0.0) as f64;
+        run.lifetime_distance = Some(totals.with(run.log_id, distance));
+        lifetime = Some(totals);
+    }
+
+    let Some(photos) = load_photos(ctx) else {
+        return Ok(());
+    };
+
+    let platforms = &ctx.arguments.platforms;
+    let view_options = ctx.arguments.view_options();
+    let Some(view_model) = view::ActivityViewModel::from_output(&run, &view_options) else {
+        println!("The run has no distance, nothing to report.");
+        return Ok(());
+    };
+    if ctx.arguments.template_all {
+        print_all_templates(ctx, &view_model, &view_options);
+        return Ok(());
+    }
+
+    let mut texts: HashMap<&str, String> = HashMap::new();
+    for platform in platforms {
+        let template = ctx.arguments.template_for(platform);
+        if texts.contains_key(template) {
+            continue;
+        }
+        match ctx.arguments.render(&view_model, template, &view_options) {
+            Ok(text) => texts.insert(template, text),
+            Err(e) if ctx.arguments.fallback_plain => {
+                eprintln!(
+                    "Warning: failed to render template {}, falling back to plain text. {}",
+                    template, e
+                );
+                texts.insert(template, view::get_plain(&view_model))
+            }
+            Err(e) => {
+                println!("Failed to create text. {}", e);
+                return Ok(());
+            }
+        };
     }
 
+    let texts = platforms
+        .iter()
+        .map(|platform| {
+            (
+                platform,
+                texts[ctx.arguments.template_for(platform)].to_owned(),
+            )
+        })
+        .collect::<Vec<(&Platform, String)>>();
     if ctx.arguments.preview {
-        println!("==== PREVIEW MODE ====");
-        println!("{}", text.unwrap());
+        print_previews(ctx, &texts);
+    } else if let Some(false) = ctx
+        .arguments
+        .post_if
+        .as_ref()
+        .map(|c| c.is_met(&run, ctx.arguments.duration_source))
+    {
+        println!("condition not met, skipping post");
     } else {
-        post_report(&ctx.arguments.platform, &ctx.config, text.unwrap()).await?;
+        // the posts that went out count even when others failed
+        let mut posted = post_texts(ctx, texts, &photos, Some(&run)).await?;
+        if posted.count > 0 {
+            posted.state.last_post = Some(state::LastPost {
+                log_id: run.log_id,
+                start_time: run.start_time.clone(),
+            });
+        }
+        state::store(&posted.state);
+        if let (true, Some(mut totals)) = (posted.count > 0, lifetime) {
+            match run.log_id {
+                Some(log_id) => {
+                    if totals.add(log_id, run.distance.unwrap_or(0.0) as f64) {
+                        totals::store(&totals);
+                    }
+                }
+                None => println!("The run has no Fitbit log id, lifetime total not updated."),
+            }
+        }
+        if let Some(e) = posted.error {
+            return Err(e);
+        }
     }
 
     Ok(())
 }
 
-async fn post_report(platform: &Platform, config: &AppConfig, text: String) -> Result<()> {
+/// Whether the platforms can take `--schedule-at`; says which cannot if not.
+fn check_schedule(ctx: &AppContext) -> bool {
+    if ctx.arguments.schedule_at.is_none() || ctx.arguments.preview {
+        return true;
+    }
+    let unsupported = ctx
+        .arguments
+        .platforms
+        .iter()
+        .find(|p| **p != Platform::Mastodon);
+    if let Some(platform) = unsupported {
+        println!("scheduling not supported for {}", platform);
+        return false;
+    }
+    true
+}
+
+/// The `--photo` images, checked against what the platforms take. `None` after saying why not.
+fn load_photos(ctx: &AppContext) -> Option<Vec<media::Photo>> {
+    let mut photos = Vec::new();
+    for path in &ctx.arguments.photos {
+        match media::load(path) {
+            Ok(photo) => photos.push(photo),
+            Err(e) => {
+                println!("Failed to load photo. {}", e);
+                return None;
+            }
+        }
+    }
+    if !photos.is_empty() {
+        for platform in &ctx.arguments.platforms {
+            match platform.media_limit() {
+                None => {
+                    println!("media not supported for {}", platform);
+                    return None;
+                }
+                Some(limit) if photos.len() > limit => {
+                    println!("{} takes at most {} photos", platform, limit);
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(photos)
+}
+
+/// Renders the run with every template; a template failing to render is reported in its
+/// place without stopping the others.
+fn print_all_templates(
+    ctx: &AppContext,
+    view_model: &view::ActivityViewModel,
+    view_options: &view::ViewOptions,
+) {
+    let names = match view::template_names() {
+        Ok(names) => names,
+        Err(e) => {
+            println!("Failed to read templates. {}", e);
+            return;
+        }
+    };
+    if !ctx.arguments.no_banner {
+        println!("==== PREVIEW MODE ====");
+    }
+    for name in &names {
+        println!("---- {} ----", name);
+        match view::get(view_model, name, view_options) {
+            Ok(text) => println!("{}", text),
+            Err(e) => println!("Failed to create text. {}\n", e),
+        }
+    }
+}
+
+fn print_previews(ctx: &AppContext, texts: &[(&Platform, String)]) {
+    if !ctx.arguments.no_banner {
+        println!("==== PREVIEW MODE ====");
+    }
+    for (platform, text) in texts {
+        if ctx.arguments.preview_box {
+            print_preview_box(platform, text);
+            continue;
+        }
+        if texts.len() > 1 {
+            println!("---- {} ----", platform);
+        }
+        println!("{}", text);
+    }
+}
+
+/// One post to make: the text for an account of a platform.
+struct Post<'a> {
+    platform: Platform,
+    account: Option<&'a Account>,
+    text: String,
+    scheduled_at: Option<DateTime<FixedOffset>>,
+    /// Sent where the platform takes one, so that a post sent again is not made twice.
+    idempotency_key: String,
+}
+
+impl<'a> Post<'a> {
+    fn new(
+        platform: &Platform,
+        account: Option<&'a Account>,
+        text: &str,
+        scheduled_at: Option<&DateTime<FixedOffset>>,
+    ) -> Self {
+        Self {
+            platform: platform.clone(),
+            account,
+            text: text.to_owned(),
+            scheduled_at: scheduled_at.cloned(),
+            idempotency_key: idempotency_key(&target_name(platform, account), text, scheduled_at),
+        }
+    }
+
+    fn name(&self) -> String {
+        target_name(&self.platform, self.account)
+    }
+}
+
+/// What came of posting the texts of a run or summary.
+struct Posted {
+    /// The state with the new posts, not yet stored.
+    state: state::State,
+    /// Number of posts that went out, the queued ones not counted.
+    count: usize,
+    /// Names every target whose post failed, `None` when none did.
+    error: Option<anyhow::Error>,
+}
+
+/// Posts each text to every account of its platform, all at once; a failed post does not
+/// stop the others. With `--outbox`, a post failing transiently is queued for
+/// `flush-outbox` instead of failing, along with the `run` it reports.
+async fn post_texts<'a>(
+    ctx: &'a AppContext<'a>,
+    texts: Vec<(&Platform, String)>,
+    photos: &[media::Photo],
+    run: Option<&fitbit::ActivityOutput>,
+) -> Result<Posted> {
+    let mut state = load_state()?;
+    let posts = texts
+        .iter()
+        .flat_map(|(platform, text)| {
+            post_accounts(ctx, platform)
+                .into_iter()
+                .map(move |account| {
+                    Post::new(platform, account, text, ctx.arguments.schedule_at.as_ref())
+                })
+        })
+        .collect::<Vec<Post>>();
+    let results = future::join_all(posts.iter().map(|post| {
+        let latest = if ctx.arguments.edit_latest {
+            state.latest_posts.get(&post.name()).cloned()
+        } else {
+            None
+        };
+        post_report(ctx, post, photos, latest)
+    }))
+    .await;
+
+    let mut count = 0;
+    let mut failures = vec![];
+    for (post, result) in posts.iter().zip(results) {
+        let name = post.name();
+        match result {
+            Ok(posted) => {
+                count += 1;
+                // the id stands in for a scheduled status, which has no page yet
+                let link = posted
+                    .as_ref()
+                    .map(|p| format!(" {}", p.url.as_deref().unwrap_or(&p.id)))
+                    .unwrap_or_default();
+                println!("{:<20} posted{}", name, link);
+                if let (true, Some(posted)) = (ctx.arguments.edit_latest, posted) {
+                    state.latest_posts.insert(name, posted.id);
+                }
+            }
+            // only Mastodon takes an idempotency key, a timeout elsewhere may have posted
+            Err(e)
+                if ctx.arguments.outbox
+                    && is_transient(&e, post.platform == Platform::Mastodon) =>
+            {
+                queue_post(ctx, post, photos, run);
+                if posts.len() > 1 {
+                    println!("{:<20} queued {}", name, describe_error(&e));
+                }
+            }
+            Err(e) => {
+                if posts.len() > 1 {
+                    println!("{:<20} failed {}", name, describe_error(&e));
+                }
+                failures.push((name, e));
+            }
+        }
+    }
+    // a single failure says best what went wrong, the table above says it for several
+    let error = match failures.len() {
+        0 => None,
+        1 => failures.pop().map(|(_, e)| e),
+        _ => {
+            let names = failures
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<&str>>();
+            Some(anyhow!("Failed to post to {}.", names.join(", ")))
+        }
+    };
+    Ok(Posted {
+        state,
+        count,
+        error,
+    })
+}
+
+fn queue_post(
+    ctx: &AppContext,
+    post: &Post,
+    photos: &[media::Photo],
+    run: Option<&fitbit::ActivityOutput>,
+) {
+    let entry = outbox::Entry {
+        platform: post.platform.to_string(),
+        account: post.account.map(|account| account.name.to_owned()),
+        text: post.text.to_owned(),
+        scheduled_at: post.scheduled_at,
+        idempotency_key: post.idempotency_key.to_owned(),
+        last_post: run.map(|run| state::LastPost {
+            log_id: run.log_id,
+            start_time: run.start_time.clone(),
+        }),
+        lifetime_distance: run
+            .filter(|_| ctx.arguments.lifetime)
+            .map(|run| run.distance.unwrap_or(0.0) as f64),
+    };
+    match outbox::append(&entry) {
+        Ok(()) => println!("Queued the {} post in outbox.jsonl.", post.name()),
+        Err(e) => println!("Failed to queue the {} post. {}", post.name(), e),
+    }
+    if !photos.is_empty() {
+        println!("Photos are not kept in the outbox, the queued post has none.");
+    }
+}
+
+/// Posts the queued posts one by one, in order, and keeps the ones that fail. A posted run
+/// becomes the last post and is added to the lifetime total, as when posted at once.
+async fn flush_outbox<'a>(ctx: &'a AppContext<'a>) -> Result<()> {
+    let entries = outbox::load();
+    if entries.is_empty() {
+        println!("The outbox is empty.");
+        return Ok(());
+    }
+    let mut state = load_state()?;
+    let mut totals = totals::load()?;
+    let mut totals_changed = false;
+    let total = entries.len();
+    let mut posted = vec![];
+    for entry in entries {
+        let Ok(platform) = <Platform as ValueEnum>::from_str(&entry.platform, true) else {
+            println!("Unknown platform {} in outbox.jsonl, kept.", entry.platform);
+            continue;
+        };
+        let account = match &entry.account {
+            Some(name) => match named_accounts(ctx.config, &platform)
+                .iter()
+                .find(|account| account.name == *name)
+            {
+                Some(account) => Some(account),
+                None => {
+                    println!("No {} account named {}, kept.", platform, name);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        // Mastodon takes a schedule only 5 minutes ahead or more, a past one is posted at once
+        let scheduled_at = entry
+            .scheduled_at
+            .filter(|t| *t > Local::now() + chrono::Duration::minutes(5));
+        let post = Post {
+            platform,
+            account,
+            text: entry.text.to_owned(),
+            scheduled_at,
+            idempotency_key: entry.idempotency_key.to_owned(),
+        };
+        if let Err(e) = post_report(ctx, &post, &[], None).await {
+            println!(
+                "Failed to post to {}, kept. {}",
+                post.name(),
+                describe_error(&e)
+            );
+            continue;
+        }
+        posted.push(entry.idempotency_key);
+        let Some(last_post) = entry.last_post else {
+            continue;
+        };
+        if let (Some(log_id), Some(distance)) = (last_post.log_id, entry.lifetime_distance) {
+            totals_changed |= totals.add(log_id, distance);
+        }
+        // a run posted since this one was queued stays the last post
+        let start_time =
+            |post: &state::LastPost| DateTime::parse_from_rfc3339(&post.start_time).ok();
+        if state
+            .last_post
+            .as_ref()
+            .is_none_or(|last| start_time(last) < start_time(&last_post))
+        {
+            state.last_post = Some(last_post);
+        }
+    }
+    state::store(&state);
+    if totals_changed {
+        totals::store(&totals);
+    }
+    println!("Posted {} of {} queued posts.", posted.len(), total);
+    outbox::remove(&posted).context("Failed to remove the posted ones from outbox.jsonl")
+}
+
+/// Accounts of the platform to post with, `None` standing for the one of the unprefixed
+/// variables. Without named accounts that one is used, and required, as before.
+fn post_accounts<'a>(ctx: &'a AppContext, platform: &Platform) -> Vec<Option<&'a Account>> {
+    let config = ctx.config;
+    let default_configured = match platform {
+        Platform::Mastodon => config.mastodon_api_url.is_some(),
+        Platform::Misskey => config.misskey_api_url.is_some(),
+        Platform::Webhook => return vec![None],
+    };
+    let named = named_accounts(config, platform);
+    let selected = |name: &str| {
+        ctx.arguments.accounts.is_empty() || ctx.arguments.accounts.iter().any(|a| a == name)
+    };
+    let mut accounts = vec![];
+    if (default_configured || named.is_empty()) && selected(DEFAULT_ACCOUNT) {
+        accounts.push(None);
+    }
+    accounts.extend(named.iter().filter(|a| selected(&a.name)).map(Some));
+    accounts
+}
+
+fn named_accounts<'a>(config: &'a AppConfig, platform: &Platform) -> &'a [Account] {
     match platform {
+        Platform::Mastodon => &config.mastodon_accounts,
+        Platform::Misskey => &config.misskey_accounts,
+        Platform::Webhook => &[],
+    }
+}
+
+/// Whether `--account` leaves every platform an account to post with; says which not if not.
+fn check_accounts(ctx: &AppContext) -> bool {
+    if ctx.arguments.preview {
+        return true;
+    }
+    let without_account = ctx
+        .arguments
+        .platforms
+        .iter()
+        .find(|p| post_accounts(ctx, p).is_empty());
+    if let Some(platform) = without_account {
+        println!(
+            "No {} account named {}.",
+            platform,
+            ctx.arguments.accounts.join(" or ")
+        );
+        return false;
+    }
+    true
+}
+
+/// Fails naming the first variable a post with the accounts to post with needs and lacks,
+/// before anything is posted.
+fn check_credentials(ctx: &AppContext) -> Result<()> {
+    if ctx.arguments.preview {
+        return Ok(());
+    }
+    let config = ctx.config;
+    for platform in &ctx.arguments.platforms {
+        let accounts = post_accounts(ctx, platform);
+        for account in accounts.iter().flatten() {
+            account.credentials()?;
+        }
+        if !accounts.iter().any(Option::is_none) {
+            continue;
+        }
+        match platform {
+            Platform::Mastodon => {
+                AppConfig::require(&config.mastodon_api_url, "MASTODON_API_URL")?;
+                AppConfig::require(&config.mastodon_access_token, "MASTODON_ACCESS_TOKEN")?;
+            }
+            Platform::Misskey => {
+                AppConfig::require(&config.misskey_api_url, "MISSKEY_API_URL")?;
+                AppConfig::require(&config.misskey_access_token, "MISSKEY_ACCESS_TOKEN")?;
+            }
+            Platform::Webhook => {
+                AppConfig::require(&config.webhook_url, "WEBHOOK_URL")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like `http::describe_error`, for an error of any kind along with what caused it.
+fn describe_error(e: &anyhow::Error) -> String {
+    let mut causes = vec![];
+    for cause in e.chain() {
+        match cause.downcast_ref::<reqwest::Error>() {
+            // reqwest's own message says enough, its sources only repeat it
+            Some(e) => {
+                causes.push(http::describe_error(e));
+                break;
+            }
+            None => causes.push(cause.to_string()),
+        }
+    }
+    causes.join(": ")
+}
+
+/// `http::is_transient` for a failed request, false for any other error.
+fn is_transient(e: &anyhow::Error, idempotent: bool) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| http::is_transient(e, idempotent))
+}
+
+/// The platform, followed by the account name for a named account, e.g. `mastodon:club`.
+fn target_name(platform: &Platform, account: Option<&Account>) -> String {
+    match account {
+        Some(account) => format!("{}:{}", platform, account.name),
+        None => platform.to_string(),
+    }
+}
+
+/// Renders the runs of the `--summary` period from --since with the summary template,
+/// and previews or posts it.
+async fn summary_report<'a>(ctx: &'a AppContext<'a>, period: summary::Period) -> Result<()> {
+    if !check_schedule(ctx) || !check_accounts(ctx) {
+        return Ok(());
+    }
+    check_credentials(ctx)?;
+    let Some(photos) = load_photos(ctx) else {
+        return Ok(());
+    };
+    let Some((fitbit_api, access_token)) = connect_fitbit(ctx).await? else {
+        return Ok(());
+    };
+
+    let start = since_date(ctx)?;
+    let end = period.end(start);
+    let activities = fitbit_api
+        .fetch_all_run_activities(&start, &access_token)
+        .await?;
+    let view_model = summary::SummaryViewModel::new(
+        start,
+        end,
+        &activities,
+        &ctx.arguments.view_options().number_format,
+    );
+    if ctx.arguments.format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&view_model).unwrap());
+        return Ok(());
+    }
+
+    let text = match ctx.arguments.render(
+        &view_model,
+        view::SUMMARY_TEMPLATE,
+        &ctx.arguments.view_options(),
+    ) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("Failed to create text. {}", e);
+            return Ok(());
+        }
+    };
+    let texts = ctx
+        .arguments
+        .platforms
+        .iter()
+        .map(|platform| (platform, text.to_owned()))
+        .collect::<Vec<(&Platform, String)>>();
+    if ctx.arguments.preview {
+        print_previews(ctx, &texts);
+    } else {
+        let posted = post_texts(ctx, texts, &photos, None).await?;
+        state::store(&posted.state);
+        if let Some(e) = posted.error {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn print_preview_box(platform: &Platform, text: &str) {
+    let count = text.trim_end().graphemes(true).count();
+    let limit = platform.char_limit();
+    let mark = if count <= limit { "✓" } else { "✗" };
+    println!("┌── {}", platform);
+    for line in text.trim_end().lines() {
+        println!("│ {}", line);
+    }
+    println!("└── {} / {} chars {}", count, limit, mark);
+}
+
+/// Same for the same post, so retrying it, or posting it again within the hour the
+/// instance remembers keys for, does not make a second one.
+fn idempotency_key(
+    target_name: &str,
+    text: &str,
+    scheduled_at: Option<&DateTime<FixedOffset>>,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    target_name.hash(&mut hasher);
+    text.hash(&mut hasher);
+    scheduled_at.map(|t| t.to_rfc3339()).hash(&mut hasher);
+    format!("running-tracker-{:016x}", hasher.finish())
+}
+
+/// Adds how many attempts a failed post took to its error.
+fn report_attempts<T>(target_name: &str, result: reqwest::Result<T>, attempts: u32) -> Result<T> {
+    result.with_context(|| {
+        format!(
+            "Posting to {} failed after {} attempt{}",
+            target_name,
+            attempts,
+            if attempts == 1 { "" } else { "s" }
+        )
+    })
+}
+
+/// A post that went out, for the platforms that keep one.
+struct PostRef {
+    id: String,
+    /// Web page of the post, when it has one yet.
+    url: Option<String>,
+}
+
+/// Posts or, with `latest`, edits. Returns the post, if the platform keeps one.
+async fn post_report<'a>(
+    ctx: &'a AppContext<'a>,
+    post: &Post<'_>,
+    photos: &[media::Photo],
+    latest: Option<String>,
+) -> Result<Option<PostRef>> {
+    let config = ctx.config;
+    let (account, text, name) = (post.account, &post.text, post.name());
+    let client = config.http.client()?;
+    let posted = match post.platform {
         Platform::Mastodon => {
-            let mastodon_api_config = mastodon::MastodonApiConfig {
-                base_url: config.mastodon_api_url.to_owned(),
-                token: config.mastodon_access_token.to_owned(),
+            let mastodon_api_config = match account {
+                Some(account) => {
+                    let (base_url, token) = account.credentials()?;
+                    mastodon::MastodonApiConfig {
+                        base_url,
+                        token,
+                        client: client.clone(),
+                    }
+                }
+                None => mastodon::MastodonApiConfig {
+                    base_url: AppConfig::require(&config.mastodon_api_url, "MASTODON_API_URL")?,
+                    token: AppConfig::require(
+                        &config.mastodon_access_token,
+                        "MASTODON_ACCESS_TOKEN",
+                    )?,
+                    client: client.clone(),
+                },
             };
-            mastodon::post(&mastodon_api_config, &text).await?;
+            let edited = match &latest {
+                Some(id) => mastodon::edit(&mastodon_api_config, id, text).await?,
+                None => None,
+            };
+            if let (Some(id), None) = (&latest, &edited) {
+                println!("Status {} cannot be edited, posting a new one.", id);
+            }
+            let status = match edited {
+                Some(status) => status,
+                None => {
+                    let mut media_ids = Vec::new();
+                    for photo in photos {
+                        media_ids.push(mastodon::upload_media(&mastodon_api_config, photo).await?);
+                    }
+                    let scheduled_at = post.scheduled_at.as_ref();
+                    let key = &post.idempotency_key;
+                    let (result, attempts) = http::retry(config.post_max_retries, true, || {
+                        mastodon::post(&mastodon_api_config, text, scheduled_at, &media_ids, key)
+                    })
+                    .await;
+                    let status = report_attempts(&name, result, attempts)?;
+                    if let Some(scheduled_at) = scheduled_at {
+                        println!(
+                            "Scheduled status {} at {}.",
+                            status.id,
+                            scheduled_at.to_rfc3339()
+                        );
+                    }
+                    status
+                }
+            };
+            PostRef {
+                id: status.id,
+                url: status.url,
+            }
         }
         Platform::Misskey => {
-            let misskey_api_config = misskey::MisskeyApiConfig {
-                base_url: config.misskey_api_url.to_owned(),
-                token: config.misskey_access_token.to_owned(),
+            let misskey_api_config = match account {
+                Some(account) => {
+                    let (base_url, token) = account.credentials()?;
+                    misskey::MisskeyApiConfig {
+                        base_url,
+                        token,
+                        client: client.clone(),
+                    }
+                }
+                None => misskey::MisskeyApiConfig {
+                    base_url: AppConfig::require(&config.misskey_api_url, "MISSKEY_API_URL")?,
+                    token: AppConfig::require(
+                        &config.misskey_access_token,
+                        "MISSKEY_ACCESS_TOKEN",
+                    )?,
+                    client: client.clone(),
+                },
+            };
+            let edited = match &latest {
+                Some(id) => misskey::edit(&misskey_api_config, id, text).await?,
+                None => None,
+            };
+            if latest.is_some() && edited.is_none() {
+                println!("notes/update is not supported by this instance, posting a new note.");
+            }
+            let id = match edited {
+                Some(id) => id,
+                None => {
+                    let mut file_ids = Vec::new();
+                    for photo in photos {
+                        file_ids.push(misskey::upload_media(&misskey_api_config, photo).await?);
+                    }
+                    // Misskey has no idempotency key, so a timed out post is not sent again
+                    let (result, attempts) = http::retry(config.post_max_retries, false, || {
+                        misskey::post(&misskey_api_config, text, &file_ids)
+                    })
+                    .await;
+                    report_attempts(&name, result, attempts)?
+                }
+            };
+            PostRef {
+                url: misskey::note_url(&misskey_api_config, &id),
+                id,
+            }
+        }
+        Platform::Webhook => {
+            let mut headers = config.webhook_headers.clone();
+            headers.extend(
+                ctx.arguments
+                    .webhook_header
+                    .iter()
+                    .cloned()
+                    .map(|(k, v)| (Some(k), v)),
+            );
+            let webhook_config = webhook::WebhookConfig {
+                url: AppConfig::require(&config.webhook_url, "WEBHOOK_URL")?,
+                headers,
+                client: client.clone(),
+            };
+            if latest.is_some() {
+                println!("webhook posts cannot be edited, posting a new one.");
+            }
+            let (result, attempts) = http::retry(config.post_max_retries, false, || {
+                webhook::post(&webhook_config, text)
+            })
+            .await;
+            report_attempts(&name, result, attempts)?;
+            return Ok(None);
+        }
+    };
+    Ok(Some(posted))
+}
+
+/// Prints or saves the activity log as Fitbit returns it, for debugging the parser.
+async fn dump_tcx<'a>(ctx: &'a AppContext<'a>) -> Result<()> {
+    let Some((fitbit_api, access_token)) = connect_fitbit(ctx).await? else {
+        return Ok(());
+    };
+
+    let log_id = match ctx.arguments.log_id {
+        Some(log_id) => log_id,
+        None => {
+            let activities = fitbit_api
+                .fetch_run_activities(&since_date(ctx)?, &access_token)
+                .await?;
+            let Some(activity) = activities.first() else {
+                println!("No run activity found.");
+                return Ok(());
             };
-            misskey::post(&misskey_api_config, &text).await?;
+            activity.logId
+        }
+    };
+    let tcx = fitbit_api
+        .fetch_activity_log(&log_id.to_string(), &access_token)
+        .await?;
+    match &ctx.arguments.output_file {
+        Some(path) => {
+            fs::write(path, tcx)
+                .with_context(|| format!("Failed to write the TCX to {}", path.display()))?;
+            println!("Wrote activity {} to {}.", log_id, path.display());
         }
+        None => print!("{}", tcx),
     }
     Ok(())
 }
 
+/// Replaces totals.json with the sum of every run since --since.
+async fn recompute_lifetime<'a>(ctx: &'a AppContext<'a>) -> Result<()> {
+    let Some((fitbit_api, access_token)) = connect_fitbit(ctx).await? else {
+        return Ok(());
+    };
+
+    let activities = fitbit_api
+        .fetch_all_run_activities(&since_date(ctx)?, &access_token)
+        .await?;
+    let mut lifetime = totals::Totals::default();
+    for activity in &activities {
+        lifetime.add(activity.logId, activity.distance.unwrap_or(0.0) as f64);
+    }
+    totals::store(&lifetime);
+    println!(
+        "Lifetime total: {:.1} km over {} runs.",
+        lifetime.distance,
+        lifetime.log_ids.len()
+    );
+    Ok(())
+}
+
+/// Runs on the cron schedule until it has no upcoming time.
+async fn serve<'a>(ctx: &'a AppContext<'a>) {
+    let metrics = Arc::new(serve::Metrics::default());
+    if let Err(e) = serve::spawn_server(ctx.arguments.port, metrics.clone()) {
+        eprintln!("Failed to start server. {}", e);
+        process::exit(1);
+    }
+    while serve::wait_for_next(&ctx.arguments.cron).await {
+        let result = run(ctx).await;
+        if let Err(e) = &result {
+            eprintln!("{}", describe_error(e));
+        }
+        metrics.record(result.is_ok());
+    }
+}
+
+fn list_templates() {
+    match view::template_names() {
+        Ok(names) => names.iter().for_each(|name| println!("{}", name)),
+        Err(e) => println!("Failed to read templates. {}", e),
+    }
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
-    let config = AppConfig::load();
+async fn main() {
     let arguments = CliArgs::parse();
+    if arguments.preview && arguments.format == OutputFormat::Json {
+        CliArgs::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--preview and --format json are mutually exclusive",
+            )
+            .exit();
+    }
+    if let (Some(resting), Some(max)) = (arguments.resting_hr, arguments.max_hr) {
+        if max <= resting {
+            CliArgs::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    "--max-hr must be greater than --resting-hr",
+                )
+                .exit();
+        }
+    }
+    let elsewhere = arguments.tcx_file.is_some()
+        || arguments.from_json.is_some()
+        || arguments.log_id.is_some()
+        || arguments.since_last_post;
+    if arguments.command.is_none() && arguments.since.is_none() && !elsewhere {
+        CliArgs::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--since is required unless --tcx-file, --from-json, --log-id or --since-last-post is given",
+            )
+            .exit();
+    }
+    if let Some(Command::Templates) = arguments.command {
+        list_templates();
+        return;
+    }
+
+    let config = match AppConfig::load(arguments.proxy.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", describe_error(&e));
+            process::exit(1);
+        }
+    };
+    if let Some(Command::Config {
+        command: ConfigCommand::Show,
+    }) = arguments.command
+    {
+        print!("{}", config);
+        return;
+    }
     let ctx = AppContext {
         config: &config,
         arguments: &arguments,
     };
 
-    run(&ctx).await
+    if arguments.serve {
+        serve(&ctx).await;
+        return;
+    }
+
+    let result = match arguments.command {
+        Some(Command::List { interactive }) => {
+            if arguments.since.is_none() && !arguments.since_last_post {
+                CliArgs::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--since or --since-last-post is required to list activities",
+                    )
+                    .exit();
+            }
+            list_activities(&ctx, interactive).await
+        }
+        Some(Command::Logout) => logout(&ctx).await,
+        Some(Command::FlushOutbox) => flush_outbox(&ctx).await,
+        _ if arguments.dump_tcx => dump_tcx(&ctx).await,
+        _ if arguments.summary.is_some() => summary_report(&ctx, arguments.summary.unwrap()).await,
+        _ if arguments.recompute_lifetime => recompute_lifetime(&ctx).await,
+        _ => run(&ctx).await,
+    };
+    if let Err(e) = result {
+        eprintln!("{}", describe_error(&e));
+        process::exit(1);
+    }
 }
@@ -0,0 +1,78 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use chrono::{Local, Utc};
+use cron::Schedule;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+
+/// Counters exposed on `/metrics` in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    runs: AtomicU64,
+    failures: AtomicU64,
+    last_run: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record(&self, succeeded: bool) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        if !succeeded {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.last_run
+            .store(Utc::now().timestamp() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self) -> String {
+        format!(
+            "running_tracker_runs_total {}\nrunning_tracker_run_failures_total {}\nrunning_tracker_last_run_timestamp_seconds {}\n",
+            self.runs.load(Ordering::Relaxed),
+            self.failures.load(Ordering::Relaxed),
+            self.last_run.load(Ordering::Relaxed),
+        )
+    }
+}
+
+async fn handle(metrics: Arc<Metrics>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match req.uri().path() {
+        "/healthz" => Response::new(Body::from("ok\n")),
+        "/metrics" => Response::new(Body::from(metrics.render())),
+        _ => {
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = StatusCode::NOT_FOUND;
+            res
+        }
+    };
+    Ok(response)
+}
+
+/// Serves `/healthz` and `/metrics` on the port in the background.
+pub fn spawn_server(port: u16, metrics: Arc<Metrics>) -> hyper::Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(metrics.clone(), req))) }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    println!("Listening on {}.", addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("Server error. {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Sleeps until the next time the schedule fires. Returns false if it never fires again.
+pub async fn wait_for_next(schedule: &Schedule) -> bool {
+    let Some(next) = schedule.upcoming(Local).next() else {
+        return false;
+    };
+    let wait = (next - Local::now()).to_std().unwrap_or_default();
+    println!("Next run at {}.", next.to_rfc3339());
+    tokio::time::sleep(wait).await;
+    true
+}